@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS invites (
+	code TEXT NOT NULL PRIMARY KEY,
+	uses_left INTEGER NOT NULL, -- the invite is removed once this reaches zero
+	created INTEGER NOT NULL -- timestamp
+);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS audit (
+	username TEXT NOT NULL,
+	time INTEGER NOT NULL, -- timestamp
+	action TEXT NOT NULL, -- e.g. "login", see AuditAction
+	success INTEGER NOT NULL, -- boolean
+
+	-- client
+	addr TEXT,
+	user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_username_time ON audit (username, time);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS sync_devices (
+	username TEXT NOT NULL,
+	device TEXT NOT NULL,
+	sync_group INTEGER NOT NULL, -- devices sharing a group share their subscriptions
+
+	UNIQUE(username, device)
+);
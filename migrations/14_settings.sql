@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS settings (
+	username TEXT NOT NULL,
+
+	scope TEXT NOT NULL, -- account, device, podcast or episode
+	target TEXT NOT NULL, -- device id or podcast url, empty for the account
+	episode TEXT NOT NULL, -- episode url, empty unless scope = episode
+
+	key TEXT NOT NULL,
+	value TEXT NOT NULL, -- json
+
+	UNIQUE(username, scope, target, episode, key)
+);
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS podcast_lists (
+	username TEXT NOT NULL,
+	name TEXT NOT NULL,
+	title TEXT NOT NULL,
+	public BOOLEAN NOT NULL DEFAULT FALSE,
+
+	UNIQUE(username, name)
+);
+
+CREATE TABLE IF NOT EXISTS podcast_list_entries (
+	username TEXT NOT NULL,
+	list TEXT NOT NULL,
+	position INTEGER NOT NULL,
+	url TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS podcast_list_entries_list ON podcast_list_entries (username, list);
@@ -0,0 +1,33 @@
+-- every episode action received, where `episodes` only holds the latest per episode
+CREATE TABLE IF NOT EXISTS episode_history (
+	username TEXT NOT NULL,
+	device TEXT,
+
+	podcast TEXT NOT NULL,
+	episode TEXT NOT NULL,
+
+	timestamp INTEGER, -- timestamp
+	guid TEXT,
+	action TEXT NOT NULL,
+	started INTEGER,
+	position INTEGER,
+	total INTEGER,
+
+	-- metadata
+	modified INTEGER NOT NULL,
+	content_hash TEXT NOT NULL,
+
+	-- resent actions are only recorded once
+	UNIQUE(username, podcast, episode, content_hash)
+);
+
+CREATE INDEX IF NOT EXISTS episode_history_user ON episode_history (username, modified);
+
+INSERT OR IGNORE INTO episode_history
+SELECT username, device,
+	podcast, episode,
+	timestamp, guid,
+	action,
+	started, position, total,
+	modified, content_hash
+FROM episodes;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS device_activity (
+	username TEXT NOT NULL,
+	device TEXT NOT NULL,
+
+	last_seen INTEGER NOT NULL, -- timestamp
+	user_agent TEXT,
+	addr TEXT,
+
+	UNIQUE(username, device)
+);
+
+CREATE TABLE IF NOT EXISTS device_calls (
+	username TEXT NOT NULL,
+	device TEXT NOT NULL,
+
+	api TEXT NOT NULL, -- e.g. "subscriptions", "episodes"
+	last_called INTEGER NOT NULL, -- timestamp
+
+	UNIQUE(username, device, api)
+);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS sessions (
+	id TEXT NOT NULL PRIMARY KEY,
+	username TEXT NOT NULL,
+
+	device TEXT, -- null until the session is used against a device
+	user_agent TEXT,
+
+	-- metadata
+	created INTEGER NOT NULL, -- timestamp
+	last_used INTEGER NOT NULL -- timestamp
+);
+
+-- carry over existing (single) sessions
+INSERT INTO sessions
+(id, username, created, last_used)
+SELECT session_id, username, unixepoch(), unixepoch()
+FROM users
+WHERE session_id IS NOT NULL;
+
+ALTER TABLE users
+DROP COLUMN session_id;
@@ -0,0 +1,6 @@
+ALTER TABLE sessions
+ADD COLUMN expires INTEGER NOT NULL DEFAULT 0; -- timestamp
+
+-- give existing sessions the previous cookie lifetime, two weeks
+UPDATE sessions
+SET expires = last_used + 1209600;
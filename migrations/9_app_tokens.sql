@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS app_tokens (
+	username TEXT NOT NULL,
+	name TEXT NOT NULL,
+	token_hash TEXT NOT NULL PRIMARY KEY, -- sha256 of the token
+
+	-- scope
+	device TEXT, -- if set, the token may only be used for this device
+	read_only INTEGER NOT NULL, -- boolean
+
+	-- metadata
+	created INTEGER NOT NULL, -- timestamp
+	last_used INTEGER, -- timestamp
+
+	UNIQUE(username, name)
+);
+
+ALTER TABLE sessions
+ADD COLUMN app_token TEXT; -- token_hash, if the session was created with an app token
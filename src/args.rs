@@ -1,9 +1,13 @@
 use std::{
     net::{AddrParseError, IpAddr, SocketAddr},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use clap::Parser;
+use axum::http::HeaderName;
+use clap::{Parser, Subcommand};
+
+use crate::{directory, lockout, proxy};
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -26,9 +30,134 @@ pub struct Args {
     #[arg(short, long, default_value_t = 80)]
     port: u16,
 
+    /// How many days a login session lasts. Sessions used
+    /// in the second half of their lifetime are renewed.
+    #[arg(long, default_value_t = 14)]
+    session_lifetime: u64,
+
+    /// How many days a login session may go unused before
+    /// it expires.
+    #[arg(long, default_value_t = 7)]
+    session_idle_timeout: u64,
+
+    /// How many failed logins for a username before it's
+    /// temporarily locked out.
+    #[arg(long, default_value_t = 5)]
+    lockout_user_threshold: u32,
+
+    /// How many failed logins from an address before it's
+    /// temporarily locked out.
+    #[arg(long, default_value_t = 20)]
+    lockout_addr_threshold: u32,
+
+    /// How many seconds the first lockout lasts. Each further
+    /// failure doubles this.
+    #[arg(long, default_value_t = 30)]
+    lockout_base: u64,
+
+    /// The longest lockout, in seconds. Failures older than
+    /// this are forgotten.
+    #[arg(long, default_value_t = 60 * 60)]
+    lockout_max: u64,
+
+    /// Allow accounts to be created over HTTP, with an invite code
+    /// from `podsync invite create`
+    #[arg(long)]
+    registration: bool,
+
+    /// The address of a trusted reverse proxy, which authenticates
+    /// users itself and passes their username in a header. May be
+    /// given more than once. Enables proxy authentication.
+    #[arg(long = "trusted-proxy")]
+    trusted_proxies: Vec<IpAddr>,
+
+    /// The header a trusted proxy passes the authenticated
+    /// username in.
+    #[arg(long, default_value = "Remote-User")]
+    proxy_user_header: HeaderName,
+
+    /// Create users authenticated by a trusted proxy, if they
+    /// don't exist yet.
+    #[arg(long)]
+    proxy_auto_provision: bool,
+
+    /// Serve the podcast directory (toplist, search & tags), built
+    /// from every user's subscriptions
+    #[arg(long)]
+    directory: bool,
+
+    /// How many users must subscribe to a podcast before the
+    /// directory lists it.
+    #[arg(long, default_value_t = 3)]
+    directory_min_subscribers: u32,
+
     /// Emit the podsync version
     #[arg(short, long)]
     version: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage users, instead of running the server
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Manage invite codes, for registering over HTTP
+    #[command(subcommand)]
+    Invite(InviteCommand),
+    /// Manage users' devices
+    #[command(subcommand)]
+    Device(DeviceCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommand {
+    /// Add a user, prompting for their password
+    Add { username: String },
+    /// Change a user's password, prompting for the new one
+    Passwd { username: String },
+    /// Delete a user, along with all their devices, subscriptions & episodes
+    Del { username: String },
+    /// List users
+    List,
+    /// Prevent a user from logging in, and end their sessions
+    Disable { username: String },
+    /// Allow a disabled user to log in again
+    Enable { username: String },
+    /// Give a user access to the admin API
+    Promote { username: String },
+    /// Remove a user's access to the admin API
+    Demote { username: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum InviteCommand {
+    /// Create an invite code
+    Create {
+        /// How many accounts the code may register
+        #[arg(long, default_value_t = 1)]
+        uses: u32,
+    },
+    /// List unused invite codes
+    List,
+    /// Revoke an invite code
+    Revoke { code: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DeviceCommand {
+    /// List a user's devices
+    List { username: String },
+    /// Retire a device, ending its sessions. Its ID may be reused afterwards
+    Retire {
+        username: String,
+        device: String,
+        /// Also remove the device's subscriptions, so synced devices hear of it
+        #[arg(long)]
+        remove_subscriptions: bool,
+    },
 }
 
 impl Args {
@@ -51,4 +180,55 @@ impl Args {
     pub fn data_dir(&self) -> Option<&Path> {
         self.data_dir.as_deref()
     }
+
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+
+    pub fn session_lifetime(&self) -> Duration {
+        days(self.session_lifetime)
+    }
+
+    pub fn session_idle_timeout(&self) -> Duration {
+        days(self.session_idle_timeout)
+    }
+
+    pub fn registration(&self) -> bool {
+        self.registration
+    }
+
+    pub fn proxy(&self) -> Option<proxy::Config> {
+        if self.trusted_proxies.is_empty() {
+            return None;
+        }
+
+        Some(proxy::Config {
+            header: self.proxy_user_header.clone(),
+            trusted: self
+                .trusted_proxies
+                .iter()
+                .map(IpAddr::to_canonical)
+                .collect(),
+            auto_provision: self.proxy_auto_provision,
+        })
+    }
+
+    pub fn directory(&self) -> Option<directory::Config> {
+        self.directory.then_some(directory::Config {
+            min_subscribers: self.directory_min_subscribers,
+        })
+    }
+
+    pub fn lockout(&self) -> lockout::Config {
+        lockout::Config {
+            user_threshold: self.lockout_user_threshold,
+            addr_threshold: self.lockout_addr_threshold,
+            base: Duration::from_secs(self.lockout_base),
+            max: Duration::from_secs(self.lockout_max),
+        }
+    }
+}
+
+fn days(n: u64) -> Duration {
+    Duration::from_secs(n * 24 * 60 * 60)
 }
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use crate::client::ClientInfo;
+use crate::time::Timestamp;
+
+/// A security-relevant event in a user's history
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "backend-sql", derive(sqlx::FromRow))]
+pub struct AuditEvent {
+    pub username: String,
+    pub time: Timestamp,
+    pub action: AuditAction,
+    pub success: bool,
+    pub addr: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "backend-sql", derive(sqlx::Type))]
+#[cfg_attr(feature = "backend-sql", sqlx(rename_all = "snake_case"))]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Authenticating with a password or app token
+    Login,
+    Logout,
+    LogoutAll,
+    /// An expired session was presented
+    Session,
+    PasswordChange,
+    /// An admin set the user's password
+    PasswordReset,
+    Register,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryAudit {
+    pub since: Option<Timestamp>,
+    /// Admins only: restrict to one user
+    pub username: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(
+        username: &str,
+        time: Timestamp,
+        action: AuditAction,
+        success: bool,
+        client: &ClientInfo,
+    ) -> Self {
+        Self {
+            username: username.into(),
+            time,
+            action,
+            success,
+            addr: client.addr.map(|a| a.to_string()),
+            user_agent: client.user_agent.clone(),
+        }
+    }
+}
@@ -3,8 +3,13 @@ use std::{
     str::FromStr,
 };
 
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
 use base64_light::base64_decode;
 use log::error;
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
 pub struct BasicAuth {
@@ -24,11 +29,90 @@ pub struct AuthAttempt {
 #[derive(PartialEq, Eq)]
 pub struct SessionId(Uuid);
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Match,
+    /// the password matched, but against a hash we want to replace
+    MatchNeedsRehash,
+    Mismatch,
+}
+
+/// Hash a password into a PHC string, e.g. `$argon2id$v=19$...`
 pub fn pwhash(s: &str) -> String {
-    sha256::digest(s)
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(s.as_bytes(), &salt)
+        .expect("argon2 hashing with default params")
+        .to_string()
+}
+
+/// Stored for users provisioned by a trusted proxy, who have no password of their own.
+/// Never matches.
+pub const NO_PASSWORD: &str = "!";
+
+pub fn verify_pwhash(pass: &str, stored: &str) -> PasswordCheck {
+    if stored == NO_PASSWORD {
+        return PasswordCheck::Mismatch;
+    }
+
+    if is_legacy_pwhash(stored) {
+        // bare, unsalted sha256 - from before we used a KDF
+        let calc = sha256::digest(pass);
+
+        return if bool::from(calc.as_bytes().ct_eq(stored.as_bytes())) {
+            PasswordCheck::MatchNeedsRehash
+        } else {
+            PasswordCheck::Mismatch
+        };
+    }
+
+    let parsed = match PasswordHash::new(stored) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("couldn't parse stored password hash: {e}");
+            return PasswordCheck::Mismatch;
+        }
+    };
+
+    // constant-time comparison is done by the verifier
+    match Argon2::default().verify_password(pass.as_bytes(), &parsed) {
+        Ok(()) => PasswordCheck::Match,
+        Err(_) => PasswordCheck::Mismatch,
+    }
+}
+
+fn is_legacy_pwhash(stored: &str) -> bool {
+    stored.len() == 64 && stored.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+const MIN_PASSWORD_LEN: usize = 8;
+/// Hashing is deliberately slow, so don't let clients hand us arbitrarily long input
+const MAX_PASSWORD_LEN: usize = 1024;
+
+/// Rules for newly chosen passwords, returning why a password is rejected
+pub fn check_new_password(username: &str, pass: &str) -> Result<(), &'static str> {
+    if pass.chars().count() < MIN_PASSWORD_LEN {
+        return Err("password must be at least 8 characters");
+    }
+    if pass.len() > MAX_PASSWORD_LEN {
+        return Err("password is too long");
+    }
+    if pass == username {
+        return Err("password mustn't be the username");
+    }
+    if crate::token::is_token(pass) {
+        // we'd treat it as an app token when logging in
+        return Err("password mustn't look like an app token");
+    }
+    Ok(())
 }
 
 impl BasicAuth {
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
     pub fn with_path_username(self, username: &str) -> Result<AuthAttempt, AuthError<'_>> {
         if self.user == username {
             Ok(AuthAttempt { auth: self })
@@ -76,10 +160,7 @@ impl FromStr for BasicAuth {
             Ok(Self { user, pass })
         };
 
-        inner().map_err(|e| {
-            error!("{}", e);
-            e
-        })
+        inner().inspect_err(|e| error!("{}", e))
     }
 }
 
@@ -88,8 +169,16 @@ impl AuthAttempt {
         &self.auth.user
     }
 
+    pub fn verify(&self, stored_pwhash: &str) -> PasswordCheck {
+        verify_pwhash(&self.auth.pass, stored_pwhash)
+    }
+
+    pub fn pass(&self) -> &str {
+        &self.auth.pass
+    }
+
     pub fn calc_pwhash(&self) -> String {
-        pwhash(&self.auth.pass[..])
+        pwhash(&self.auth.pass)
     }
 }
 
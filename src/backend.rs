@@ -4,6 +4,12 @@ pub enum FindError {
     Internal,
 }
 
+#[derive(Debug)]
+pub enum CreateError {
+    AlreadyExists,
+    Internal,
+}
+
 #[cfg(feature = "backend-sql")]
 mod backend_sql;
 #[cfg(feature = "backend-sql")]
@@ -1,20 +1,29 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufRead, BufReader, ErrorKind, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 
-use crate::backend::FindError;
+use crate::audit::AuditEvent;
+use crate::backend::{CreateError, FindError};
 
-use crate::device::{DeviceAndSub, DeviceType, DeviceUpdate};
+use crate::device::{ApiCall, DeviceActivity, DeviceAndSub, DeviceType, DeviceUpdate, SyncRequest};
 use crate::episode::{Episode, EpisodeRaw};
+use crate::invite::Invite;
+use crate::podcast_list::{ListInfo, PodcastList};
 use crate::podsync::{QueryEpisodes, Url};
+use crate::sanitize::sanitize;
+use crate::session::Session;
+use crate::settings::{Settings, SettingsTarget, SettingsUpdate, FAVORITE};
 use crate::subscription::SubscriptionChangesFromClient;
-use crate::user::User;
+use crate::token::AppToken;
+use crate::user::{User, UserSummary};
 use crate::Timestamp;
 
 mod kv;
@@ -24,11 +33,17 @@ pub struct Backend {
     root: PathBuf,
 }
 
+/// Sessions from before expiry was tracked get the old cookie lifetime
+const LEGACY_SESSION_LIFETIME: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
 impl Backend {
     pub async fn new(path: &Path) -> Self {
-        Self {
+        let backend = Self {
             root: path.to_path_buf(),
-        }
+        };
+        backend.migrate_sessions();
+        backend.sanitize_stored_urls();
+        backend
     }
 }
 
@@ -72,9 +87,37 @@ impl Backend {
         kv::write(file, keyvalues)
     }
 
+    /// List the file names in a directory, treating a missing directory as empty
+    fn list_dir(&self, path: PathBuf) -> Result<Vec<String>, ()> {
+        let emap = |e: &dyn std::fmt::Debug| {
+            error!("error listing \"{path:?}\": {e:?}");
+        };
+
+        let entries = match fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                emap(&e);
+                return Err(());
+            }
+        };
+
+        let mut names = vec![];
+        for ent in entries {
+            let ent = ent.map_err(|e| emap(&e))?;
+
+            match ent.file_name().into_string() {
+                Ok(name) => names.push(name),
+                Err(e) => warn!("couldn't convert path into string: {e:?}"),
+            }
+        }
+
+        Ok(names)
+    }
+
     fn read_user(&self, username: &str) -> Result<KeyValues, FindError> {
         let path = path!(self.root, "users", username, "creds.txt");
-        self.read(path, &["pwhash", "session_id"])
+        self.read(path, &["pwhash", "disabled", "admin"])
     }
 
     fn write_user(&self, username: &str, keyvalues: &KeyValues) -> Result<(), std::io::Error> {
@@ -90,69 +133,907 @@ impl Backend {
         Ok(User {
             username: target_username.into(),
             pwhash: user.get("pwhash").ok_or(FindError::Internal)?.clone(),
-            session_id: user.get("session_id").map(|x| x.into()),
+            disabled: user.get("disabled").map(String::as_str) == Some("true"),
+            is_admin: user.get("admin").map(String::as_str) == Some("true"),
+        })
+    }
+
+    fn usernames(&self) -> Result<Vec<String>, ()> {
+        let mut names = self.list_dir(path!(self.root, "users"))?;
+        names.sort();
+        Ok(names)
+    }
+
+    pub async fn users(&self) -> Result<Vec<User>, ()> {
+        let mut users = vec![];
+
+        for username in self.usernames()? {
+            let user = self.find_user(&username).await.map_err(|e| {
+                error!("read \"{username}\": {e:?}");
+            })?;
+            users.push(user);
+        }
+
+        Ok(users)
+    }
+
+    pub async fn create_user(&self, username: &str, pwhash: &str) -> Result<(), CreateError> {
+        let dir = path!(self.root, "users", username);
+
+        fs::create_dir_all(path!(self.root, "users")).map_err(|e| {
+            error!("couldn't create users directory: {e:?}");
+            CreateError::Internal
+        })?;
+        fs::create_dir(&dir).map_err(|e| {
+            if e.kind() == ErrorKind::AlreadyExists {
+                return CreateError::AlreadyExists;
+            }
+            error!("couldn't create \"{dir:?}\": {e:?}");
+            CreateError::Internal
+        })?;
+
+        for file in ["devices.txt", "subs.txt", "episodes.txt"] {
+            File::create(path!(dir, file)).map_err(|e| {
+                error!("couldn't create \"{username}\"'s {file}: {e:?}");
+                CreateError::Internal
+            })?;
+        }
+
+        let mut creds = KeyValues::new();
+        creds.insert("pwhash".into(), pwhash.into());
+
+        self.write_user(username, &creds).map_err(|e| {
+            error!("write \"{username}\": {e:?}");
+            CreateError::Internal
+        })
+    }
+
+    pub async fn set_user_disabled(&self, username: &str, disabled: bool) -> Result<(), FindError> {
+        let mut user = self.read_user(username)?;
+
+        match disabled {
+            true => user.insert("disabled".into(), "true".into()),
+            false => user.remove("disabled"),
+        };
+
+        self.write_user(username, &user).map_err(|e| {
+            error!("write \"{username}\": {e:?}");
+            FindError::Internal
+        })
+    }
+
+    pub async fn set_user_admin(&self, username: &str, is_admin: bool) -> Result<(), FindError> {
+        let mut user = self.read_user(username)?;
+
+        match is_admin {
+            true => user.insert("admin".into(), "true".into()),
+            false => user.remove("admin"),
+        };
+
+        self.write_user(username, &user).map_err(|e| {
+            error!("write \"{username}\": {e:?}");
+            FindError::Internal
+        })
+    }
+
+    pub async fn user_summaries(&self) -> Result<Vec<UserSummary>, ()> {
+        let mut summaries = vec![];
+
+        for user in self.users().await? {
+            let devices = self.devices(&user.username)?.len();
+            let subscriptions = self
+                .subscriptions_anydev(&user.username)?
+                .into_iter()
+                .filter(|(_, _, _, deleted)| deleted.is_none())
+                .map(|(_, url, _, _)| url)
+                .collect::<HashSet<_>>()
+                .len();
+
+            summaries.push(UserSummary {
+                username: user.username,
+                disabled: user.disabled,
+                is_admin: user.is_admin,
+                devices: devices as u32,
+                subscriptions: subscriptions as u32,
+            });
+        }
+
+        Ok(summaries)
+    }
+
+    /// Remove a user, along with all their sessions, devices, subscriptions & episodes
+    pub async fn delete_user(&self, username: &str) -> Result<(), FindError> {
+        // ensure they exist, before we remove anything
+        self.read_user(username)?;
+
+        self.delete_sessions_for_user(username, None)
+            .await
+            .map_err(|()| FindError::Internal)?;
+        for token in self
+            .tokens_for_user(username)
+            .await
+            .map_err(|()| FindError::Internal)?
+        {
+            self.delete_token(username, &token.name).await?;
+        }
+
+        let dir = path!(self.root, "users", username);
+        fs::remove_dir_all(&dir).map_err(|e| {
+            error!("couldn't remove \"{dir:?}\": {e:?}");
+            FindError::Internal
+        })
+    }
+
+    pub async fn update_pwhash(&self, username: &str, pwhash: &str) -> bool {
+        let mut user = match self.read_user(username) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("read \"{username}\": {e:?}");
+                return false;
+            }
+        };
+
+        user.insert("pwhash".into(), pwhash.into());
+
+        if let Err(e) = self.write_user(username, &user) {
+            error!("write \"{username}\": {e:?}");
+            false
+        } else {
+            true
+        }
+    }
+}
+
+impl Backend {
+    fn session_path(&self, session_id: &str) -> PathBuf {
+        path!(self.root, "sessions", session_id)
+    }
+
+    fn write_session(&self, session: &Session) -> Result<(), ()> {
+        let mut kv = KeyValues::new();
+        kv.insert("username".into(), session.username.clone());
+        if let Some(ref device) = session.device {
+            kv.insert("device".into(), device.clone());
+        }
+        if let Some(ref user_agent) = session.user_agent {
+            kv.insert("user_agent".into(), user_agent.clone());
+        }
+        kv.insert("created".into(), session.created.to_string());
+        kv.insert("last_used".into(), session.last_used.to_string());
+        kv.insert("expires".into(), session.expires.to_string());
+        if let Some(ref app_token) = session.app_token {
+            kv.insert("app_token".into(), app_token.clone());
+        }
+
+        let dir = path!(self.root, "sessions");
+        fs::create_dir_all(&dir).map_err(|e| {
+            error!("couldn't create \"{dir:?}\": {e:?}");
+        })?;
+
+        self.write(self.session_path(&session.id), &kv)
+            .map_err(|e| {
+                error!("write session {}: {e:?}", session.id);
+            })
+    }
+
+    fn session_ids(&self) -> Result<Vec<String>, ()> {
+        self.list_dir(path!(self.root, "sessions"))
+    }
+
+    /// sessions used to live in each user's creds.txt, move them to sessions/
+    fn migrate_sessions(&self) {
+        let path = path!(self.root, "users");
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+
+        for ent in entries.flatten() {
+            let Ok(username) = ent.file_name().into_string() else {
+                continue;
+            };
+            let Ok(mut user) = self.read_user(&username) else {
+                continue;
+            };
+            let Some(session_id) = user.remove("session_id") else {
+                continue;
+            };
+
+            let now = match Timestamp::now() {
+                Ok(now) => now,
+                Err(e) => {
+                    error!("couldn't create timestamp: {e:?}");
+                    return;
+                }
+            };
+            let session = Session {
+                id: session_id,
+                username: username.clone(),
+                device: None,
+                user_agent: None,
+                created: now,
+                last_used: now,
+                expires: now + LEGACY_SESSION_LIFETIME,
+                app_token: None,
+            };
+
+            if self.write_session(&session).is_err() {
+                continue;
+            }
+            match self.write_user(&username, &user) {
+                Ok(()) => info!("migrated session for \"{username}\""),
+                Err(e) => error!("write \"{username}\": {e:?}"),
+            }
+        }
+    }
+
+    pub async fn create_session(&self, session: &Session) -> Result<(), ()> {
+        self.write_session(session)
+    }
+
+    pub async fn find_session(&self, session_id: &str) -> Result<Session, FindError> {
+        if session_id.contains(std::path::is_separator) || session_id.starts_with('.') {
+            return Err(FindError::NotFound);
+        }
+
+        let kv = self.read(
+            self.session_path(session_id),
+            &[
+                "username",
+                "device",
+                "user_agent",
+                "created",
+                "last_used",
+                "expires",
+                "app_token",
+            ],
+        )?;
+
+        let parse = |k: &str| {
+            kv.get(k)
+                .ok_or_else(|| {
+                    error!("session {session_id}: no \"{k}\"");
+                    FindError::Internal
+                })?
+                .parse::<Timestamp>()
+                .map_err(|()| FindError::Internal)
+        };
+
+        let last_used = parse("last_used")?;
+        let expires = match kv.contains_key("expires") {
+            true => parse("expires")?,
+            false => last_used + LEGACY_SESSION_LIFETIME,
+        };
+
+        Ok(Session {
+            id: session_id.into(),
+            username: kv.get("username").ok_or(FindError::Internal)?.clone(),
+            device: kv.get("device").cloned(),
+            user_agent: kv.get("user_agent").cloned(),
+            created: parse("created")?,
+            last_used,
+            expires,
+            app_token: kv.get("app_token").cloned(),
+        })
+    }
+
+    pub async fn touch_session(
+        &self,
+        session_id: &str,
+        now: Timestamp,
+        device: Option<&str>,
+    ) -> Result<(), ()> {
+        let mut session = self.find_session(session_id).await.map_err(|e| {
+            error!("couldn't find session {session_id}: {e:?}");
+        })?;
+
+        session.last_used = now;
+        if let Some(device) = device {
+            session.device = Some(device.into());
+        }
+
+        self.write_session(&session)
+    }
+
+    pub async fn update_session_expiry(
+        &self,
+        session_id: &str,
+        expires: Timestamp,
+    ) -> Result<(), ()> {
+        let mut session = self.find_session(session_id).await.map_err(|e| {
+            error!("couldn't find session {session_id}: {e:?}");
+        })?;
+
+        session.expires = expires;
+        self.write_session(&session)
+    }
+
+    pub async fn delete_session(&self, session_id: &str) -> Result<(), ()> {
+        match fs::remove_file(self.session_path(session_id)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                error!("couldn't remove session {session_id}: {e:?}");
+                Err(())
+            }
+        }
+    }
+
+    /// End all of a user's sessions, except (optionally) the one in use
+    pub async fn delete_sessions_for_user(
+        &self,
+        username: &str,
+        except: Option<&str>,
+    ) -> Result<(), ()> {
+        for id in self.session_ids()? {
+            let session = match self.find_session(&id).await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!("skipping unreadable session {id}: {e:?}");
+                    continue;
+                }
+            };
+
+            if session.username == username && except != Some(id.as_str()) {
+                self.delete_session(&id).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Backend {
+    fn token_path(&self, token_hash: &str) -> PathBuf {
+        path!(self.root, "tokens", token_hash)
+    }
+
+    fn write_token(&self, token: &AppToken) -> Result<(), ()> {
+        let mut kv = KeyValues::new();
+        kv.insert("username".into(), token.username.clone());
+        kv.insert("name".into(), token.name.clone());
+        if let Some(ref device) = token.device {
+            kv.insert("device".into(), device.clone());
+        }
+        kv.insert("read_only".into(), token.read_only.to_string());
+        kv.insert("created".into(), token.created.to_string());
+        if let Some(ref last_used) = token.last_used {
+            kv.insert("last_used".into(), last_used.to_string());
+        }
+
+        let dir = path!(self.root, "tokens");
+        fs::create_dir_all(&dir).map_err(|e| {
+            error!("couldn't create \"{dir:?}\": {e:?}");
+        })?;
+
+        self.write(self.token_path(&token.token_hash), &kv)
+            .map_err(|e| {
+                error!("write token for {}: {e:?}", token.username);
+            })
+    }
+
+    pub async fn create_token(&self, token: &AppToken) -> Result<(), CreateError> {
+        let existing = self
+            .tokens_for_user(&token.username)
+            .await
+            .map_err(|()| CreateError::Internal)?;
+
+        if existing.iter().any(|t| t.name == token.name) {
+            return Err(CreateError::AlreadyExists);
+        }
+
+        self.write_token(token).map_err(|()| CreateError::Internal)
+    }
+
+    pub async fn find_token(&self, token_hash: &str) -> Result<AppToken, FindError> {
+        if !token_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(FindError::NotFound);
+        }
+
+        let kv = self.read(
+            self.token_path(token_hash),
+            &[
+                "username",
+                "name",
+                "device",
+                "read_only",
+                "created",
+                "last_used",
+            ],
+        )?;
+
+        let get = |k: &str| {
+            kv.get(k).ok_or_else(|| {
+                error!("token: no \"{k}\"");
+                FindError::Internal
+            })
+        };
+        let parse = |s: &str| s.parse::<Timestamp>().map_err(|()| FindError::Internal);
+
+        Ok(AppToken {
+            username: get("username")?.clone(),
+            name: get("name")?.clone(),
+            token_hash: token_hash.into(),
+            device: kv.get("device").cloned(),
+            read_only: get("read_only")? == "true",
+            created: parse(get("created")?)?,
+            last_used: kv.get("last_used").map(|s| parse(s)).transpose()?,
+        })
+    }
+
+    pub async fn tokens_for_user(&self, username: &str) -> Result<Vec<AppToken>, ()> {
+        let mut tokens = vec![];
+
+        for hash in self.list_dir(path!(self.root, "tokens"))? {
+            let token = match self.find_token(&hash).await {
+                Ok(t) => t,
+                Err(e) => {
+                    warn!("skipping unreadable token: {e:?}");
+                    continue;
+                }
+            };
+
+            if token.username == username {
+                tokens.push(token);
+            }
+        }
+        tokens.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(tokens)
+    }
+
+    pub async fn touch_token(&self, token_hash: &str, now: Timestamp) -> Result<(), ()> {
+        let mut token = self.find_token(token_hash).await.map_err(|e| {
+            error!("couldn't find token: {e:?}");
+        })?;
+
+        token.last_used = Some(now);
+        self.write_token(&token)
+    }
+
+    /// Revoke a token, ending any sessions created with it
+    pub async fn delete_token(&self, username: &str, name: &str) -> Result<(), FindError> {
+        let tokens = self
+            .tokens_for_user(username)
+            .await
+            .map_err(|()| FindError::Internal)?;
+        let token = tokens
+            .iter()
+            .find(|t| t.name == name)
+            .ok_or(FindError::NotFound)?;
+
+        for id in self.session_ids().map_err(|()| FindError::Internal)? {
+            let Ok(session) = self.find_session(&id).await else {
+                continue;
+            };
+
+            if session.app_token.as_ref() == Some(&token.token_hash) {
+                self.delete_session(&id)
+                    .await
+                    .map_err(|()| FindError::Internal)?;
+            }
+        }
+
+        let path = self.token_path(&token.token_hash);
+        fs::remove_file(&path).map_err(|e| {
+            error!("couldn't remove \"{path:?}\": {e:?}");
+            FindError::Internal
+        })
+    }
+}
+
+impl Backend {
+    fn read_podcast_lists(&self, username: &str) -> Result<Vec<PodcastList>, ()> {
+        let path = path!(self.root, "users", username, "lists.jsonl");
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                error!("open \"{path:?}\": {e:?}");
+                return Err(());
+            }
+        };
+
+        let mut lists = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| {
+                error!("read \"{path:?}\": {e:?}");
+            })?;
+
+            match serde_json::from_str(&line) {
+                Ok(list) => lists.push(list),
+                Err(e) => warn!("skipping invalid podcast list line: {e:?}"),
+            }
+        }
+
+        Ok(lists)
+    }
+
+    fn write_podcast_lists(&self, username: &str, lists: &[PodcastList]) -> Result<(), ()> {
+        let mut contents = String::new();
+        for list in lists {
+            contents += &serde_json::to_string(list).map_err(|e| {
+                error!("couldn't serialise podcast list: {e:?}");
+            })?;
+            contents.push('\n');
+        }
+
+        let path = path!(self.root, "users", username, "lists.jsonl");
+        fs::write(&path, contents).map_err(|e| {
+            error!("write \"{path:?}\": {e:?}");
+        })
+    }
+
+    pub async fn podcast_lists(&self, username: &str) -> Result<Vec<ListInfo>, ()> {
+        let mut lists = self
+            .read_podcast_lists(username)?
+            .into_iter()
+            .map(|list| list.info)
+            .collect::<Vec<_>>();
+        lists.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(lists)
+    }
+
+    pub async fn podcast_list(&self, username: &str, name: &str) -> Result<PodcastList, FindError> {
+        self.read_podcast_lists(username)
+            .map_err(|()| FindError::Internal)?
+            .into_iter()
+            .find(|list| list.info.name == name)
+            .ok_or(FindError::NotFound)
+    }
+
+    pub async fn create_podcast_list(
+        &self,
+        username: &str,
+        list: &PodcastList,
+    ) -> Result<(), CreateError> {
+        let mut lists = self
+            .read_podcast_lists(username)
+            .map_err(|()| CreateError::Internal)?;
+        if lists.iter().any(|l| l.info.name == list.info.name) {
+            return Err(CreateError::AlreadyExists);
+        }
+
+        lists.push(list.clone());
+        self.write_podcast_lists(username, &lists)
+            .map_err(|()| CreateError::Internal)
+    }
+
+    /// Replace an existing list's title, visibility & URLs
+    pub async fn update_podcast_list(
+        &self,
+        username: &str,
+        list: &PodcastList,
+    ) -> Result<(), FindError> {
+        let mut lists = self
+            .read_podcast_lists(username)
+            .map_err(|()| FindError::Internal)?;
+        let existing = lists
+            .iter_mut()
+            .find(|l| l.info.name == list.info.name)
+            .ok_or(FindError::NotFound)?;
+
+        *existing = list.clone();
+        self.write_podcast_lists(username, &lists)
+            .map_err(|()| FindError::Internal)
+    }
+
+    pub async fn delete_podcast_list(&self, username: &str, name: &str) -> Result<(), FindError> {
+        let mut lists = self
+            .read_podcast_lists(username)
+            .map_err(|()| FindError::Internal)?;
+        let before = lists.len();
+        lists.retain(|l| l.info.name != name);
+        if lists.len() == before {
+            return Err(FindError::NotFound);
+        }
+
+        self.write_podcast_lists(username, &lists)
+            .map_err(|()| FindError::Internal)
+    }
+}
+
+/// A target's settings, one per line of a user's settings.jsonl
+#[derive(Debug, Serialize, Deserialize)]
+struct SettingsEntry {
+    scope: String,
+    target: String,
+    episode: String,
+    settings: Settings,
+}
+
+impl SettingsEntry {
+    fn is_for(&self, target: &SettingsTarget) -> bool {
+        let (scope, t, episode) = target.key();
+        self.scope == scope && self.target == t && self.episode == episode
+    }
+}
+
+impl Backend {
+    fn read_settings(&self, username: &str) -> Result<Vec<SettingsEntry>, ()> {
+        let path = path!(self.root, "users", username, "settings.jsonl");
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                error!("open \"{path:?}\": {e:?}");
+                return Err(());
+            }
+        };
+
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| {
+                error!("read \"{path:?}\": {e:?}");
+            })?;
+
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("skipping invalid settings line: {e:?}"),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn settings(&self, username: &str, target: &SettingsTarget) -> Result<Settings, ()> {
+        Ok(self
+            .read_settings(username)?
+            .into_iter()
+            .find(|entry| entry.is_for(target))
+            .map(|entry| entry.settings)
+            .unwrap_or_default())
+    }
+
+    /// (username, podcast, value) for every user's podcast-scope `key` setting
+    pub async fn podcast_setting_values(
+        &self,
+        key: &str,
+    ) -> Result<Vec<(String, String, serde_json::Value)>, ()> {
+        let mut values = vec![];
+
+        for username in self.usernames()? {
+            for mut entry in self.read_settings(&username)? {
+                if entry.scope != "podcast" {
+                    continue;
+                }
+                if let Some(value) = entry.settings.remove(key) {
+                    values.push((username.clone(), entry.target, value));
+                }
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// (podcast, episode) pairs marked as favourites
+    pub async fn favorites(&self, username: &str) -> Result<Vec<(String, String)>, ()> {
+        Ok(self
+            .read_settings(username)?
+            .into_iter()
+            .filter(|entry| {
+                entry.scope == "episode"
+                    && entry.settings.get(FAVORITE) == Some(&serde_json::Value::Bool(true))
+            })
+            .map(|entry| (entry.target, entry.episode))
+            .collect())
+    }
+
+    pub async fn update_settings(
+        &self,
+        username: &str,
+        target: &SettingsTarget,
+        update: &SettingsUpdate,
+    ) -> Result<(), ()> {
+        let mut entries = self.read_settings(username)?;
+
+        let index = match entries.iter().position(|entry| entry.is_for(target)) {
+            Some(i) => i,
+            None => {
+                let (scope, t, episode) = target.key();
+                entries.push(SettingsEntry {
+                    scope: scope.into(),
+                    target: t.into(),
+                    episode: episode.into(),
+                    settings: Settings::new(),
+                });
+                entries.len() - 1
+            }
+        };
+
+        let settings = &mut entries[index].settings;
+        for key in &update.remove {
+            settings.remove(key);
+        }
+        for (key, value) in &update.set {
+            settings.insert(key.clone(), value.clone());
+        }
+        entries.retain(|entry| !entry.settings.is_empty());
+
+        self.write_settings(username, &entries)
+    }
+
+    fn write_settings(&self, username: &str, entries: &[SettingsEntry]) -> Result<(), ()> {
+        let mut contents = String::new();
+        for entry in entries {
+            contents += &serde_json::to_string(entry).map_err(|e| {
+                error!("couldn't serialise settings: {e:?}");
+            })?;
+            contents.push('\n');
+        }
+
+        let path = path!(self.root, "users", username, "settings.jsonl");
+        fs::write(&path, contents).map_err(|e| {
+            error!("write \"{path:?}\": {e:?}");
+        })
+    }
+}
+
+impl Backend {
+    fn audit_path(&self) -> PathBuf {
+        path!(self.root, "audit.jsonl")
+    }
+
+    pub async fn add_audit_event(&self, event: &AuditEvent) -> Result<(), ()> {
+        let path = self.audit_path();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| {
+                error!("open \"{path:?}\": {e:?}");
+            })?;
+
+        let mut line = serde_json::to_string(event).map_err(|e| {
+            error!("couldn't serialise audit event: {e:?}");
+        })?;
+        line.push('\n');
+
+        // a single write, so concurrent appends don't interleave
+        file.write_all(line.as_bytes()).map_err(|e| {
+            error!("write \"{path:?}\": {e:?}");
+        })
+    }
+
+    /// Events after `since`, oldest first, for one user or everyone
+    pub async fn audit_events(
+        &self,
+        username: Option<&str>,
+        since: Timestamp,
+    ) -> Result<Vec<AuditEvent>, ()> {
+        let path = self.audit_path();
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                error!("open \"{path:?}\": {e:?}");
+                return Err(());
+            }
+        };
+
+        let mut events = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| {
+                error!("read \"{path:?}\": {e:?}");
+            })?;
+
+            let event: AuditEvent = match serde_json::from_str(&line) {
+                Ok(e) => e,
+                Err(e) => {
+                    warn!("skipping invalid audit line: {e:?}");
+                    continue;
+                }
+            };
+
+            if event.time > since && username.is_none_or(|u| event.username == u) {
+                events.push(event);
+            }
+        }
+        // stable, so same-second events keep their order
+        events.sort_by_key(|e| e.time);
+
+        Ok(events)
+    }
+}
+
+impl Backend {
+    fn invite_path(&self, code: &str) -> PathBuf {
+        path!(self.root, "invites", code)
+    }
+
+    fn write_invite(&self, invite: &Invite) -> Result<(), ()> {
+        let mut kv = KeyValues::new();
+        kv.insert("uses_left".into(), invite.uses_left.to_string());
+        kv.insert("created".into(), invite.created.to_string());
+
+        let dir = path!(self.root, "invites");
+        fs::create_dir_all(&dir).map_err(|e| {
+            error!("couldn't create \"{dir:?}\": {e:?}");
+        })?;
+
+        self.write(self.invite_path(&invite.code), &kv)
+            .map_err(|e| {
+                error!("write invite: {e:?}");
+            })
+    }
+
+    pub async fn create_invite(&self, invite: &Invite) -> Result<(), ()> {
+        self.write_invite(invite)
+    }
+
+    pub async fn find_invite(&self, code: &str) -> Result<Invite, FindError> {
+        if code.is_empty() || !code.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(FindError::NotFound);
+        }
+
+        let kv = self.read(self.invite_path(code), &["uses_left", "created"])?;
+
+        let get = |k: &str| {
+            kv.get(k).ok_or_else(|| {
+                error!("invite: no \"{k}\"");
+                FindError::Internal
+            })
+        };
+
+        Ok(Invite {
+            code: code.into(),
+            uses_left: get("uses_left")?.parse().map_err(|e| {
+                error!("invite: invalid uses_left: {e:?}");
+                FindError::Internal
+            })?,
+            created: get("created")?.parse().map_err(|()| FindError::Internal)?,
         })
     }
 
-    /// session_id: set to None to logout / make NULL
-    pub async fn update_user(&self, username: &str, session_id: Option<&str>) -> bool {
-        let mut user = match self.read_user(username) {
-            Ok(u) => u,
-            Err(e) => {
-                error!("read \"{username}\": {e:?}");
-                return false;
-            }
-        };
+    pub async fn invites(&self) -> Result<Vec<Invite>, ()> {
+        let mut invites = vec![];
 
-        match session_id {
-            Some(id) => {
-                user.insert("session_id".into(), id.into());
-            }
-            None => {
-                user.remove("session_id");
+        for code in self.list_dir(path!(self.root, "invites"))? {
+            match self.find_invite(&code).await {
+                Ok(invite) => invites.push(invite),
+                Err(e) => warn!("skipping unreadable invite: {e:?}"),
             }
         }
+        invites.sort_by_key(|i| i.created);
 
-        if let Err(e) = self.write_user(username, &user) {
-            error!("write \"{username}\": {e:?}");
-            false
-        } else {
-            true
-        }
+        Ok(invites)
     }
 
-    pub async fn users_with_session(&self, session_id: &str) -> Result<Vec<User>, ()> {
-        let path = path!(self.root, "users");
-        let mut users = vec![];
-
-        let emap = |e: &dyn std::fmt::Debug| {
-            error!("error looking up session: {e:?}");
-        };
+    /// Use up one of an invite's uses, removing it once they're all gone
+    pub async fn use_invite(&self, code: &str) -> Result<(), FindError> {
+        let mut invite = self.find_invite(code).await?;
+        if invite.uses_left == 0 {
+            return Err(FindError::NotFound);
+        }
 
-        for ent in fs::read_dir(path).map_err(|e| emap(&e))? {
-            let ent = ent.map_err(|e| emap(&e))?;
+        invite.uses_left -= 1;
+        if invite.uses_left > 0 {
+            return self.write_invite(&invite).map_err(|()| FindError::Internal);
+        }
 
-            let fname = ent.file_name();
-            let fname = match fname.into_string() {
-                Ok(x) => x,
-                Err(e) => {
-                    warn!("couldn't convert path into string: {e:?}");
-                    continue;
-                }
-            };
+        self.delete_invite(code).await
+    }
 
-            let u = self.find_user(&fname).await.map_err(|e| emap(&e))?;
-            if let Some(ref id) = u.session_id {
-                if id == session_id {
-                    users.push(u);
-                }
-            }
-        }
+    pub async fn delete_invite(&self, code: &str) -> Result<(), FindError> {
+        // validates the code
+        self.find_invite(code).await?;
 
-        Ok(users)
+        let path = self.invite_path(code);
+        fs::remove_file(&path).map_err(|e| {
+            error!("couldn't remove \"{path:?}\": {e:?}");
+            FindError::Internal
+        })
     }
 }
 
+/// A device's activity, one per line of a user's device_activity.jsonl
+#[derive(Debug, Serialize, Deserialize)]
+struct ActivityEntry {
+    device: String,
+    #[serde(flatten)]
+    activity: DeviceActivity,
+}
+
 impl Backend {
     fn devices(&self, username: &str) -> Result<Vec<(String, DeviceType, String)>, ()> {
         let path = path!(self.root, "users", username, "devices.txt");
@@ -185,16 +1066,28 @@ impl Backend {
     }
 
     pub async fn devices_for_user(&self, username: &str) -> Result<Vec<DeviceAndSub>, ()> {
-        let subcount = self.subscriptions_anydev(username)?.len(); // inefficient
+        let subs = self.subscriptions_anydev(username)?;
+        let mut activity = self.read_device_activity(username)?;
 
         self.devices(username)?
             .into_iter()
             .map(|(id, type_, caption)| {
+                let subcount = subs
+                    .iter()
+                    .filter(|(dev, _, _, deleted)| *dev == id && deleted.is_none())
+                    .count();
+                let activity = activity
+                    .iter()
+                    .position(|entry| entry.device == id)
+                    .map(|i| activity.swap_remove(i).activity)
+                    .unwrap_or_default();
+
                 Ok(DeviceAndSub {
                     r#type: type_,
                     id,
                     caption,
                     subscriptions: subcount as _,
+                    activity,
                 })
             })
             .collect::<Result<Vec<_>, _>>()
@@ -230,6 +1123,179 @@ impl Backend {
             ));
         }
 
+        self.write_devices(username, &devices)
+    }
+
+    /// Remove a device, its place in any sync group and its sessions, optionally
+    /// marking its subscriptions deleted at `now`
+    pub async fn retire_device(
+        &self,
+        username: &str,
+        device_id: &str,
+        now: Timestamp,
+        remove_subscriptions: bool,
+    ) -> Result<(), FindError> {
+        let mut devices = self.devices(username).map_err(|()| FindError::Internal)?;
+        let count = devices.len();
+        devices.retain(|(id, _, _)| id != device_id);
+        if devices.len() == count {
+            return Err(FindError::NotFound);
+        }
+
+        let leave = SyncRequest {
+            synchronize: vec![],
+            stop_synchronize: vec![device_id.into()],
+        };
+        let groups = self
+            .sync_groups(username)
+            .await
+            .map_err(|()| FindError::Internal)?;
+        self.set_sync_groups(username, &leave.apply(groups))
+            .await
+            .map_err(|()| FindError::Internal)?;
+
+        for id in self.session_ids().map_err(|()| FindError::Internal)? {
+            let Ok(session) = self.find_session(&id).await else {
+                continue;
+            };
+
+            if session.username == username && session.device.as_deref() == Some(device_id) {
+                self.delete_session(&id)
+                    .await
+                    .map_err(|()| FindError::Internal)?;
+            }
+        }
+
+        let tokens = self
+            .tokens_for_user(username)
+            .await
+            .map_err(|()| FindError::Internal)?;
+        for token in tokens {
+            if token.device.as_deref() == Some(device_id) {
+                self.delete_token(username, &token.name).await?;
+            }
+        }
+
+        if remove_subscriptions {
+            let changes = SubscriptionChangesFromClient {
+                add: vec![],
+                remove: self
+                    .current_subscriptions(username, Some(device_id))
+                    .await
+                    .map_err(|()| FindError::Internal)?,
+            };
+            self.update_subscriptions(username, device_id, &changes, now)
+                .await
+                .map_err(|()| FindError::Internal)?;
+        }
+
+        let mut activity = self
+            .read_device_activity(username)
+            .map_err(|()| FindError::Internal)?;
+        activity.retain(|entry| entry.device != device_id);
+        self.write_device_activity(username, &activity)
+            .map_err(|()| FindError::Internal)?;
+
+        let mut settings = self
+            .read_settings(username)
+            .map_err(|()| FindError::Internal)?;
+        settings.retain(|entry| !entry.is_for(&SettingsTarget::Device(device_id.into())));
+        self.write_settings(username, &settings)
+            .map_err(|()| FindError::Internal)?;
+
+        self.write_devices(username, &devices)
+            .map_err(|()| FindError::Internal)
+    }
+
+    /// Record that a device was just seen, calling `call` - unless it isn't registered
+    pub async fn touch_device(
+        &self,
+        username: &str,
+        device_id: &str,
+        call: ApiCall,
+        now: Timestamp,
+        user_agent: Option<&str>,
+        addr: Option<&str>,
+    ) -> Result<(), ()> {
+        if !self
+            .devices(username)?
+            .iter()
+            .any(|(id, _, _)| id == device_id)
+        {
+            return Ok(());
+        }
+
+        let mut entries = self.read_device_activity(username)?;
+        let i = match entries.iter().position(|entry| entry.device == device_id) {
+            Some(i) => i,
+            None => {
+                entries.push(ActivityEntry {
+                    device: device_id.into(),
+                    activity: Default::default(),
+                });
+                entries.len() - 1
+            }
+        };
+
+        let activity = &mut entries[i].activity;
+        activity.last_seen = Some(now);
+        if let Some(user_agent) = user_agent {
+            activity.user_agent = Some(user_agent.into());
+        }
+        if let Some(addr) = addr {
+            activity.addr = Some(addr.into());
+        }
+        activity.api_calls.insert(call.as_str().into(), now);
+
+        self.write_device_activity(username, &entries)
+    }
+
+    fn read_device_activity(&self, username: &str) -> Result<Vec<ActivityEntry>, ()> {
+        let path = path!(self.root, "users", username, "device_activity.jsonl");
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                error!("open \"{path:?}\": {e:?}");
+                return Err(());
+            }
+        };
+
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| {
+                error!("read \"{path:?}\": {e:?}");
+            })?;
+
+            match serde_json::from_str(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!("skipping invalid device activity line: {e:?}"),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn write_device_activity(&self, username: &str, entries: &[ActivityEntry]) -> Result<(), ()> {
+        let mut contents = String::new();
+        for entry in entries {
+            contents += &serde_json::to_string(entry).map_err(|e| {
+                error!("couldn't serialise device activity: {e:?}");
+            })?;
+            contents.push('\n');
+        }
+
+        let path = path!(self.root, "users", username, "device_activity.jsonl");
+        fs::write(&path, contents).map_err(|e| {
+            error!("write \"{path:?}\": {e:?}");
+        })
+    }
+
+    fn write_devices(
+        &self,
+        username: &str,
+        devices: &[(String, DeviceType, String)],
+    ) -> Result<(), ()> {
         let path = path!(self.root, "users", username, "devices.txt");
         let mut file = OpenOptions::new()
             .write(true)
@@ -240,8 +1306,7 @@ impl Backend {
                 error!("couldn't open \"{username}\"'s devices: {e:?}");
             })?;
 
-        for dev in devices {
-            let (id, type_, caption) = dev;
+        for (id, type_, caption) in devices {
             writeln!(file, "{id} {} {caption}", type_.as_str()).map_err(|e| {
                 error!("writing \"{username}\" devices: {e:?}");
             })?;
@@ -252,6 +1317,52 @@ impl Backend {
 }
 
 impl Backend {
+    /// Groups of devices which share their subscriptions, one group per line
+    pub async fn sync_groups(&self, username: &str) -> Result<Vec<Vec<String>>, ()> {
+        let path = path!(self.root, "users", username, "sync.txt");
+        let file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                error!("open \"{path:?}\": {e:?}");
+                return Err(());
+            }
+        };
+
+        BufReader::new(file)
+            .lines()
+            .map(|line| {
+                line.map(|l| l.split(' ').map(str::to_string).collect())
+                    .map_err(|e| {
+                        error!("read \"{path:?}\": {e:?}");
+                    })
+            })
+            .collect()
+    }
+
+    pub async fn set_sync_groups(&self, username: &str, groups: &[Vec<String>]) -> Result<(), ()> {
+        let path = path!(self.root, "users", username, "sync.txt");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| {
+                error!("couldn't open \"{username}\"'s sync groups: {e:?}");
+            })?;
+
+        for group in groups {
+            writeln!(file, "{}", group.join(" ")).map_err(|e| {
+                error!("writing \"{username}\" sync groups: {e:?}");
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Backend {
+    #[allow(clippy::type_complexity)]
     fn subscriptions_anydev(
         &self,
         username: &str,
@@ -321,6 +1432,39 @@ impl Backend {
             .collect())
     }
 
+    /// Every actively subscribed URL, with how many users subscribe to it
+    pub async fn podcast_subscribers(&self) -> Result<Vec<(String, u32)>, ()> {
+        let mut subscribers = std::collections::HashMap::<String, u32>::new();
+
+        for username in self.usernames()? {
+            for url in self.current_subscriptions(&username, None).await? {
+                *subscribers.entry(url).or_default() += 1;
+            }
+        }
+
+        Ok(subscribers.into_iter().collect())
+    }
+
+    /// The URLs subscribed to now, on one device or any
+    pub async fn current_subscriptions(
+        &self,
+        username: &str,
+        device_id: Option<&str>,
+    ) -> Result<Vec<String>, ()> {
+        let mut urls = self
+            .subscriptions_anydev(username)?
+            .into_iter()
+            .filter(|(dev, _, _, deleted)| {
+                deleted.is_none() && device_id.is_none_or(|id| dev == id)
+            })
+            .map(|(_, url, _, _)| url)
+            .collect::<Vec<_>>();
+        urls.sort();
+        urls.dedup();
+
+        Ok(urls)
+    }
+
     pub async fn update_subscriptions(
         &self,
         username: &str,
@@ -330,22 +1474,47 @@ impl Backend {
     ) -> Result<(), ()> {
         let existing = self.subscriptions_anydev(username)?;
         let urls_to_del = changes.remove.iter().collect::<HashSet<_>>();
-        let now = Timestamp::now().map_err(|e| {
-            error!("couldn't create timestamp: {e:?}");
-        })?;
+        let active = existing
+            .iter()
+            .filter(|(dev, _, _, deleted)| dev == device_id && deleted.is_none())
+            .map(|(_, url, _, _)| url)
+            .collect::<HashSet<_>>();
+
         let new_subs = changes
             .add
             .iter()
+            .filter(|url| !active.contains(url))
+            .collect::<HashSet<_>>()
+            .into_iter()
             .map(|url| (device_id, url.as_str(), &now, None));
 
+        // removals are kept, marked deleted, so other devices hear about them
         let to_write = existing
             .iter()
-            .filter(|sub| sub.0 != device_id || !urls_to_del.contains(&sub.1))
             .map(|(dev, url, created, deleted)| {
-                (dev.as_str(), url.as_str(), created, deleted.as_ref())
+                let removed = dev == device_id && deleted.is_none() && urls_to_del.contains(url);
+
+                (
+                    dev.as_str(),
+                    url.as_str(),
+                    created,
+                    if removed {
+                        Some(&now)
+                    } else {
+                        deleted.as_ref()
+                    },
+                )
             })
             .chain(new_subs);
 
+        self.write_subs(username, to_write)
+    }
+
+    fn write_subs<'s>(
+        &self,
+        username: &str,
+        subs: impl Iterator<Item = (&'s str, &'s str, &'s Timestamp, Option<&'s Timestamp>)>,
+    ) -> Result<(), ()> {
         let path = path!(self.root, "users", username, "subs.txt");
         let mut file = OpenOptions::new()
             .write(true)
@@ -356,7 +1525,7 @@ impl Backend {
                 error!("couldn't open \"{username}\"'s subs: {e:?}");
             })?;
 
-        for sub in to_write {
+        for sub in subs {
             let (device, url, created, deleted) = sub;
 
             let r = match deleted {
@@ -374,13 +1543,103 @@ impl Backend {
 }
 
 impl Backend {
-    pub async fn episodes(
-        &self,
-        username: &str,
-        query: &QueryEpisodes,
-    ) -> Result<Vec<EpisodeRaw>, ()> {
-        let path = path!(self.root, "users", username, "episodes.txt");
-        let file = File::open(&path).map_err(|e| {
+    /// Rewrite subscription & episode URLs stored before they were sanitised,
+    /// merging subscriptions which now duplicate each other. Only runs until every
+    /// user's URLs have been rewritten, which leaves a marker file behind.
+    fn sanitize_stored_urls(&self) {
+        let marker = path!(self.root, "urls_sanitised");
+        if marker.exists() {
+            return;
+        }
+        let Ok(usernames) = self.usernames() else {
+            return;
+        };
+
+        let mut complete = true;
+        for username in usernames {
+            match self.sanitize_user_urls(&username) {
+                Ok(0) => {}
+                Ok(n) => info!("sanitised {n} stored urls for \"{username}\""),
+                Err(()) => {
+                    error!("couldn't sanitise \"{username}\"'s urls, retrying on the next start");
+                    complete = false;
+                }
+            }
+        }
+
+        if complete {
+            if let Err(e) = File::create(&marker) {
+                error!("couldn't create \"{marker:?}\": {e:?}");
+            }
+        }
+    }
+
+    fn sanitize_user_urls(&self, username: &str) -> Result<usize, ()> {
+        let mut rewritten = 0;
+
+        let mut subs = self.subscriptions_anydev(username)?;
+        for (_, url, _, _) in &mut subs {
+            if let Some(new) = sanitize(url).filter(|new| new != url) {
+                *url = new;
+                rewritten += 1;
+            }
+        }
+        if rewritten > 0 {
+            let mut seen = HashSet::new();
+            subs.retain(|(dev, url, _, deleted)| seen.insert((dev.clone(), url.clone(), *deleted)));
+
+            self.write_subs(
+                username,
+                subs.iter().map(|(dev, url, created, deleted)| {
+                    (dev.as_str(), url.as_str(), created, deleted.as_ref())
+                }),
+            )?;
+        }
+
+        // episodes are merged when read, keeping the latest action per episode
+        let history_path = path!(self.root, "users", username, "episode_history.jsonl");
+        let episodes_path = path!(self.root, "users", username, "episodes.txt");
+        for path in [history_path.clone(), episodes_path] {
+            if !path.exists() {
+                continue;
+            }
+
+            let mut eps = self.read_episodes(&path, username)?;
+            let mut changed = 0;
+            for ep in &mut eps {
+                let podcast = sanitize(&ep.podcast).filter(|p| *p != ep.podcast);
+                let episode = sanitize(&ep.episode).filter(|e| *e != ep.episode);
+                if podcast.is_none() && episode.is_none() {
+                    continue;
+                }
+
+                ep.podcast = podcast.unwrap_or_else(|| ep.podcast.clone());
+                ep.episode = episode.unwrap_or_else(|| ep.episode.clone());
+                changed += 1;
+            }
+
+            if changed > 0 {
+                self.write_episodes(&path, username, &eps)?;
+                rewritten += changed;
+
+                if path == history_path {
+                    // rebuilt from the rewritten history when next needed
+                    let hashes = path!(self.root, "users", username, "episode_history.hashes");
+                    if let Err(e) = fs::remove_file(&hashes) {
+                        if e.kind() != ErrorKind::NotFound {
+                            error!("couldn't remove \"{hashes:?}\": {e:?}");
+                            return Err(());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(rewritten)
+    }
+
+    fn read_episodes(&self, path: &Path, username: &str) -> Result<Vec<EpisodeRaw>, ()> {
+        let file = File::open(path).map_err(|e| {
             error!("open \"{path:?}\": {e:?}");
         })?;
 
@@ -392,7 +1651,7 @@ impl Backend {
             })?;
 
             let ep = serde_json::from_str(&line).map_err(|e| {
-                error!("couldn't parse episode line for {username}");
+                error!("couldn't parse episode line for {username}: {e:?}");
             })?;
             eps.push(ep);
         }
@@ -400,19 +1659,98 @@ impl Backend {
         Ok(eps)
     }
 
+    /// Every action received, oldest first. Episodes from before the history was kept
+    /// (so without a history file) have only their latest action.
+    fn episode_history(&self, username: &str) -> Result<Vec<EpisodeRaw>, ()> {
+        let path = path!(self.root, "users", username, "episode_history.jsonl");
+        if path.exists() {
+            self.read_episodes(&path, username)
+        } else {
+            self.read_episodes(
+                &path!(self.root, "users", username, "episodes.txt"),
+                username,
+            )
+        }
+    }
+
+    pub async fn episodes(
+        &self,
+        username: &str,
+        query: &QueryEpisodes,
+    ) -> Result<Vec<EpisodeRaw>, ()> {
+        let eps = if query.is_aggregated() {
+            let path = path!(self.root, "users", username, "episodes.txt");
+            let eps = self.read_episodes(&path, username)?;
+
+            // only the latest action per (podcast, episode), by the client's timestamp:
+            // actions without one lose to those with, and ties go to the last received
+            let mut latest = HashMap::new();
+            for (i, ep) in eps.iter().enumerate() {
+                let current = latest.entry((&ep.podcast, &ep.episode)).or_insert(i);
+                if ep.timestamp >= eps[*current].timestamp {
+                    *current = i;
+                }
+            }
+            let latest = latest.into_values().collect::<HashSet<_>>();
+
+            eps.iter()
+                .enumerate()
+                .filter(|(i, _)| latest.contains(i))
+                .map(|(_, ep)| ep.clone())
+                .collect()
+        } else {
+            self.episode_history(username)?
+        };
+
+        // episodes stored before modification times were recorded are always included
+        let since = query.since.unwrap_or_else(Timestamp::zero);
+        Ok(eps
+            .into_iter()
+            .filter(|ep| ep.modified.is_none_or(|modified| modified > since))
+            .filter(|ep| query.podcast.as_ref().is_none_or(|p| *p == ep.podcast))
+            .filter(|ep| {
+                query
+                    .device
+                    .as_ref()
+                    .is_none_or(|d| ep.device.as_ref() == Some(d))
+            })
+            .collect())
+    }
+
     pub async fn update_episodes(
         &self,
         username: &str,
         now: Timestamp,
         changes: Vec<Episode>,
     ) -> Result<(), ()> {
-        let mut eps = self.episodes(username, &QueryEpisodes::default()).await?;
+        let mut eps = self.read_episodes(
+            &path!(self.root, "users", username, "episodes.txt"),
+            username,
+        )?;
+        let history_path = path!(self.root, "users", username, "episode_history.jsonl");
+        if !history_path.exists() {
+            // start the history with the actions from before it was kept
+            let old = self.episode_history(username)?;
+            self.write_episodes(&history_path, username, &old)?;
+        }
+
+        // resent actions are only recorded once
+        let mut recorded = self.episode_history_hashes(username)?;
+        let mut history = vec![];
+        let mut hashes = vec![];
 
         for change in changes {
             // insert `change`, if conflict then replace
             // supplement with username, device, podcast
 
-            let change: EpisodeRaw = change.into();
+            let hash = change.hash();
+            let change: EpisodeRaw = (change, now).into();
+
+            if recorded.insert(hash.clone()) {
+                history.push(change.clone());
+                hashes.push(hash);
+            }
+
             let change_id = change.id();
             let found = eps.iter_mut().find(|ep| ep.id() == change_id);
 
@@ -426,18 +1764,87 @@ impl Backend {
             }
         }
 
-        let path = path!(self.root, "users", username, "episodes.txt");
+        self.write_episodes(
+            &path!(self.root, "users", username, "episodes.txt"),
+            username,
+            &eps,
+        )?;
+        self.append_episodes(&history_path, username, &history)?;
+        self.append_history_hashes(username, &hashes)
+    }
+
+    /// The content hash of each action in the history, as the SQL backend's
+    /// `content_hash`. Kept in a file alongside the history, rebuilt from it if missing.
+    fn episode_history_hashes(&self, username: &str) -> Result<HashSet<String>, ()> {
+        let path = path!(self.root, "users", username, "episode_history.hashes");
+        match File::open(&path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .collect::<Result<_, _>>()
+                .map_err(|e| {
+                    error!("read \"{path:?}\": {e:?}");
+                }),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let hashes = self
+                    .episode_history(username)?
+                    .into_iter()
+                    .filter_map(|ep| Episode::try_from(ep).ok())
+                    .map(|ep| ep.hash())
+                    .collect::<Vec<_>>();
+                self.append_history_hashes(username, &hashes)?;
+                Ok(hashes.into_iter().collect())
+            }
+            Err(e) => {
+                error!("open \"{path:?}\": {e:?}");
+                Err(())
+            }
+        }
+    }
+
+    fn append_history_hashes(&self, username: &str, hashes: &[String]) -> Result<(), ()> {
+        let path = path!(self.root, "users", username, "episode_history.hashes");
         let mut file = OpenOptions::new()
-            .write(true)
+            .append(true)
             .create(true)
-            .truncate(true)
-            .open(path)
+            .open(&path)
             .map_err(|e| {
-                error!("couldn't open \"{username}\"'s episodes: {e:?}");
+                error!("couldn't open \"{path:?}\": {e:?}");
+            })?;
+
+        for hash in hashes {
+            writeln!(file, "{hash}").map_err(|e| {
+                error!("writing \"{path:?}\": {e:?}");
             })?;
+        }
+
+        Ok(())
+    }
+
+    fn write_episodes(&self, path: &Path, username: &str, eps: &[EpisodeRaw]) -> Result<(), ()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        self.output_episodes(&options, path, username, eps)
+    }
+
+    fn append_episodes(&self, path: &Path, username: &str, eps: &[EpisodeRaw]) -> Result<(), ()> {
+        let mut options = OpenOptions::new();
+        options.append(true).create(true);
+        self.output_episodes(&options, path, username, eps)
+    }
+
+    fn output_episodes(
+        &self,
+        options: &OpenOptions,
+        path: &Path,
+        username: &str,
+        eps: &[EpisodeRaw],
+    ) -> Result<(), ()> {
+        let mut file = options.open(path).map_err(|e| {
+            error!("couldn't open \"{username}\"'s episodes: {e:?}");
+        })?;
 
         for ep in eps {
-            let json = serde_json::to_string(&ep).map_err(|e| {
+            let json = serde_json::to_string(ep).map_err(|e| {
                 error!("couldn't convert episode to json: {e:?}");
             })?;
 
@@ -451,4 +1858,157 @@ impl Backend {
 }
 
 #[cfg(test)]
-pub mod test {}
+pub mod test {
+    use super::*;
+
+    /// A backend in a fresh directory, removed by the caller with `remove_backend`
+    pub async fn create_backend() -> Backend {
+        let root = std::env::temp_dir().join(format!("podsync-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&root).unwrap();
+        Backend::new(&root).await
+    }
+
+    pub fn remove_backend(backend: Backend) {
+        fs::remove_dir_all(&backend.root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sanitizes_stored_urls_once() {
+        let backend = create_backend().await;
+        backend.create_user("bob", "").await.unwrap();
+        let subscribe = |url: &str| SubscriptionChangesFromClient {
+            add: vec![url.into()],
+            remove: vec![],
+        };
+        let now = Timestamp::now().unwrap();
+
+        // stored before URLs were sanitised - once they have been, there's no rescan
+        fs::remove_file(path!(backend.root, "urls_sanitised")).unwrap();
+        backend
+            .update_subscriptions("bob", "phone", &subscribe("https://Feed.example/x"), now)
+            .await
+            .unwrap();
+        let backend = Backend::new(&backend.root).await;
+        backend
+            .update_subscriptions("bob", "phone", &subscribe("https://Feed.example/y"), now)
+            .await
+            .unwrap();
+        let backend = Backend::new(&backend.root).await;
+
+        assert_eq!(
+            backend
+                .current_subscriptions("bob", Some("phone"))
+                .await
+                .unwrap(),
+            ["https://Feed.example/y", "https://feed.example/x"]
+        );
+        remove_backend(backend);
+    }
+
+    #[tokio::test]
+    async fn stale_episode_actions() {
+        let backend = create_backend().await;
+        backend.create_user("bob", "").await.unwrap();
+        let upload = |action: &str| {
+            let json = format!(
+                r#"{{ "podcast": "https://a.example", "episode": "https://a.example/ep1", {action} }}"#
+            );
+            let backend = &backend;
+            async move {
+                let now = Timestamp::now().unwrap();
+                let change = serde_json::from_str(&json).unwrap();
+                backend
+                    .update_episodes("bob", now, vec![change])
+                    .await
+                    .unwrap();
+            }
+        };
+        let latest = || async {
+            let eps = backend
+                .episodes("bob", &QueryEpisodes::default())
+                .await
+                .unwrap();
+            assert_eq!(eps.len(), 1);
+            let action = serde_json::to_value(&eps[0].action).unwrap();
+            (action.as_str().unwrap().to_string(), eps[0].position)
+        };
+
+        upload(r#""action": "play", "timestamp": "2024-01-01T12:00:00", "started": 0, "position": 50, "total": 100"#).await;
+
+        // a stale download doesn't replace the later play, nor does an action without a timestamp
+        upload(r#""action": "download", "timestamp": "2024-01-01T11:00:00""#).await;
+        assert_eq!(latest().await, ("play".to_string(), Some(50)));
+        upload(r#""action": "play", "started": 0, "position": 5, "total": 100"#).await;
+        assert_eq!(latest().await, ("play".to_string(), Some(50)));
+
+        // but a newer one does
+        upload(r#""action": "download", "timestamp": "2024-01-01T13:00:00""#).await;
+        assert_eq!(latest().await, ("download".to_string(), None));
+
+        remove_backend(backend);
+    }
+
+    #[tokio::test]
+    async fn episode_history() {
+        let backend = create_backend().await;
+        backend.create_user("bob", "").await.unwrap();
+        let upload = |time: &str| {
+            let json = format!(
+                r#"{{ "podcast": "https://a.example", "episode": "https://a.example/ep1",
+                      "action": "download", "timestamp": "2024-01-01T{time}" }}"#
+            );
+            let backend = &backend;
+            async move {
+                let now = Timestamp::now().unwrap();
+                let change = serde_json::from_str(&json).unwrap();
+                backend
+                    .update_episodes("bob", now, vec![change])
+                    .await
+                    .unwrap();
+            }
+        };
+        let history = || async {
+            let query = QueryEpisodes {
+                aggregated: Some(false),
+                ..Default::default()
+            };
+            backend
+                .episodes("bob", &query)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|ep| serde_json::to_value(ep.timestamp).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // history from before it was kept is the latest action per episode
+        upload("10:00:00").await;
+        let root = backend.root.clone();
+        fs::remove_file(path!(root, "users", "bob", "episode_history.jsonl")).unwrap();
+        fs::remove_file(path!(root, "users", "bob", "episode_history.hashes")).unwrap();
+
+        // resent actions are only recorded once, including those from before the history
+        upload("10:00:00").await;
+        upload("11:00:00").await;
+        upload("11:00:00").await;
+        assert_eq!(
+            history().await,
+            ["2024-01-01T10:00:00", "2024-01-01T11:00:00"]
+        );
+
+        // the hashes are rebuilt if lost
+        fs::remove_file(path!(root, "users", "bob", "episode_history.hashes")).unwrap();
+        upload("11:00:00").await;
+        upload("12:00:00").await;
+        assert_eq!(
+            history().await,
+            [
+                "2024-01-01T10:00:00",
+                "2024-01-01T11:00:00",
+                "2024-01-01T12:00:00"
+            ]
+        );
+
+        remove_backend(backend);
+    }
+}
@@ -22,7 +22,7 @@ pub fn read(input: impl Read, keys: &[&str]) -> Result<KeyValues, FindError> {
         })?;
 
         let (k, v) = parts;
-        if v.chars().next() != Some(' ') {
+        if !v.starts_with(' ') {
             error!("invalid line - no whitespace after colon");
             return Err(FindError::Internal);
         }
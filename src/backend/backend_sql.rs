@@ -5,18 +5,28 @@ use sqlx::{migrate::MigrateDatabase, query, query_as, Pool, Sqlite, SqlitePool,
 
 use log::{error, info};
 
-use crate::backend::FindError;
-use crate::device::{DeviceAndSub, DeviceUpdate};
-use crate::episode::{Episode, EpisodeRaw};
+use crate::audit::AuditEvent;
+use crate::backend::{CreateError, FindError};
+use crate::device::{ApiCall, DeviceActivity, DeviceAndSub, DeviceType, DeviceUpdate};
+use crate::episode::{Episode, EpisodeRaw, Time};
+use crate::invite::Invite;
+use crate::podcast_list::{ListInfo, PodcastList};
 use crate::podsync::{QueryEpisodes, Url};
+use crate::sanitize::sanitize;
+use crate::session::Session;
+use crate::settings::{Settings, SettingsTarget, SettingsUpdate, FAVORITE};
 use crate::subscription::SubscriptionChangesFromClient;
-use crate::user::User;
+use crate::token::AppToken;
+use crate::user::{User, UserSummary};
 use crate::Timestamp;
 
 type Result<T> = std::result::Result<T, ()>;
 
 pub struct Backend(pub Pool<Sqlite>);
 
+/// The database's `user_version` once its stored URLs have been sanitised
+const SANITISED_URLS_VERSION: i64 = 1;
+
 fn into_sql(path: &Path) -> PathBuf {
     path.join("pod.sql")
 }
@@ -57,7 +67,11 @@ impl Backend {
             .await
             .expect("migration");
 
-        Self(pool)
+        let backend = Self(pool);
+        if backend.sanitize_stored_urls().await.is_err() {
+            error!("couldn't sanitise stored urls, retrying on the next start");
+        }
+        backend
     }
 }
 
@@ -85,12 +99,1320 @@ impl Backend {
     pub async fn find_user(&self, username: &str) -> std::result::Result<User, FindError> {
         query_as!(
             User,
-            "
-                SELECT *
+            r#"
+                SELECT username, pwhash,
+                disabled as "disabled: bool",
+                is_admin as "is_admin: bool"
                 FROM users
                 WHERE username = ?
-                ",
+                "#,
+            username,
+        )
+        .fetch_one(&self.0)
+        .await
+        .map_err(|e| {
+            if matches!(e, sqlx::Error::RowNotFound) {
+                FindError::NotFound
+            } else {
+                FindError::Internal
+            }
+        })
+    }
+
+    pub async fn users(&self) -> Result<Vec<User>> {
+        query_as!(
+            User,
+            r#"
+            SELECT username, pwhash,
+                disabled as "disabled: bool",
+                is_admin as "is_admin: bool"
+            FROM users
+            ORDER BY username
+            "#,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting users: {e:?}");
+        })
+    }
+
+    pub async fn create_user(
+        &self,
+        username: &str,
+        pwhash: &str,
+    ) -> std::result::Result<(), CreateError> {
+        query!(
+            "
+            INSERT INTO users
+            (username, pwhash)
+            VALUES
+            (?, ?)
+            ",
+            username,
+            pwhash,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => {
+                CreateError::AlreadyExists
+            }
+            e => {
+                error!("error inserting user: {e:?}");
+                CreateError::Internal
+            }
+        })
+    }
+
+    pub async fn set_user_disabled(
+        &self,
+        username: &str,
+        disabled: bool,
+    ) -> std::result::Result<(), FindError> {
+        let result = query!(
+            "
+            UPDATE users
+            SET disabled = ?
+            WHERE username = ?
+            ",
+            disabled,
+            username,
+        )
+        .execute(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error updating user: {e:?}");
+            FindError::Internal
+        })?;
+
+        match result.rows_affected() {
+            0 => Err(FindError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    /// Remove a user, along with all their sessions, devices, subscriptions & episodes
+    pub async fn delete_user(&self, username: &str) -> std::result::Result<(), FindError> {
+        let found = self
+            .transact(|mut tx| async {
+                let result = query!("DELETE FROM users WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user: {e:?}");
+                    })?;
+                let found = result.rows_affected() > 0;
+
+                query!("DELETE FROM sessions WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's sessions: {e:?}");
+                    })?;
+                query!("DELETE FROM app_tokens WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's tokens: {e:?}");
+                    })?;
+                query!("DELETE FROM devices WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's devices: {e:?}");
+                    })?;
+                query!("DELETE FROM sync_devices WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's sync groups: {e:?}");
+                    })?;
+                query!("DELETE FROM device_activity WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's device activity: {e:?}");
+                    })?;
+                query!("DELETE FROM device_calls WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's device calls: {e:?}");
+                    })?;
+                query!("DELETE FROM settings WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's settings: {e:?}");
+                    })?;
+                query!("DELETE FROM podcast_lists WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's podcast lists: {e:?}");
+                    })?;
+                query!(
+                    "DELETE FROM podcast_list_entries WHERE username = ?",
+                    username
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting user's podcast list entries: {e:?}");
+                })?;
+                query!("DELETE FROM subscriptions WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's subscriptions: {e:?}");
+                    })?;
+                query!("DELETE FROM episode_history WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's episode history: {e:?}");
+                    })?;
+                query!("DELETE FROM episodes WHERE username = ?", username)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting user's episodes: {e:?}");
+                    })?;
+
+                Ok((tx, found))
+            })
+            .await
+            .map_err(|()| FindError::Internal)?;
+
+        match found {
+            true => Ok(()),
+            false => Err(FindError::NotFound),
+        }
+    }
+
+    pub async fn user_summaries(&self) -> Result<Vec<UserSummary>> {
+        query_as!(
+            UserSummary,
+            r#"
+            SELECT username,
+                disabled as "disabled: bool",
+                is_admin as "is_admin: bool",
+                (
+                    SELECT COUNT(*)
+                    FROM devices
+                    WHERE devices.username = users.username
+                ) as "devices!: u32",
+                (
+                    SELECT COUNT(DISTINCT url)
+                    FROM subscriptions
+                    WHERE subscriptions.username = users.username
+                    AND deleted IS NULL
+                ) as "subscriptions!: u32"
+            FROM users
+            ORDER BY username
+            "#,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error summarising users: {e:?}");
+        })
+    }
+
+    pub async fn set_user_admin(
+        &self,
+        username: &str,
+        is_admin: bool,
+    ) -> std::result::Result<(), FindError> {
+        let result = query!(
+            "
+            UPDATE users
+            SET is_admin = ?
+            WHERE username = ?
+            ",
+            is_admin,
+            username,
+        )
+        .execute(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error updating user: {e:?}");
+            FindError::Internal
+        })?;
+
+        match result.rows_affected() {
+            0 => Err(FindError::NotFound),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn update_pwhash(&self, username: &str, pwhash: &str) -> bool {
+        query!(
+            "
+            UPDATE users
+            SET pwhash = ?
+            WHERE username = ?
+            ",
+            pwhash,
+            username,
+        )
+        .execute(&self.0)
+        .await
+        .map_err(|e| {
+            error!("update pwhash: {e}");
+            e
+        })
+        .is_ok()
+    }
+}
+
+impl Backend {
+    pub async fn create_session(&self, session: &Session) -> Result<()> {
+        query!(
+            "
+            INSERT INTO sessions
+            (id, username, device, user_agent, created, last_used, expires, app_token)
+            VALUES
+            (?, ?, ?, ?, ?, ?, ?, ?)
+            ",
+            session.id,
+            session.username,
+            session.device,
+            session.user_agent,
+            session.created,
+            session.last_used,
+            session.expires,
+            session.app_token,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error inserting session: {e:?}");
+        })
+    }
+
+    pub async fn find_session(&self, session_id: &str) -> std::result::Result<Session, FindError> {
+        query_as!(
+            Session,
+            r#"
+            SELECT id, username, device, user_agent,
+                created as "created: _",
+                last_used as "last_used: _",
+                expires as "expires: _",
+                app_token
+            FROM sessions
+            WHERE id = ?
+            "#,
+            session_id,
+        )
+        .fetch_one(&self.0)
+        .await
+        .map_err(|e| {
+            if matches!(e, sqlx::Error::RowNotFound) {
+                FindError::NotFound
+            } else {
+                error!("couldn't query for session {session_id}: {e:?}");
+                FindError::Internal
+            }
+        })
+    }
+
+    /// device: set to None to leave the session's device as-is
+    pub async fn touch_session(
+        &self,
+        session_id: &str,
+        now: Timestamp,
+        device: Option<&str>,
+    ) -> Result<()> {
+        query!(
+            "
+            UPDATE sessions
+            SET
+                last_used = ?,
+                device = coalesce(?, device)
+            WHERE id = ?
+            ",
+            now,
+            device,
+            session_id,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error updating session: {e:?}");
+        })
+    }
+
+    pub async fn update_session_expiry(&self, session_id: &str, expires: Timestamp) -> Result<()> {
+        query!(
+            "
+            UPDATE sessions
+            SET expires = ?
+            WHERE id = ?
+            ",
+            expires,
+            session_id,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error updating session expiry: {e:?}");
+        })
+    }
+
+    pub async fn delete_session(&self, session_id: &str) -> Result<()> {
+        query!(
+            "
+            DELETE FROM sessions
+            WHERE id = ?
+            ",
+            session_id,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error deleting session: {e:?}");
+        })
+    }
+
+    /// End all of a user's sessions, except (optionally) the one in use
+    pub async fn delete_sessions_for_user(
+        &self,
+        username: &str,
+        except: Option<&str>,
+    ) -> Result<()> {
+        query!(
+            "
+            DELETE FROM sessions
+            WHERE username = ? AND id IS NOT ?
+            ",
+            username,
+            except,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error deleting sessions for {username}: {e:?}");
+        })
+    }
+}
+
+impl Backend {
+    pub async fn add_audit_event(&self, event: &AuditEvent) -> Result<()> {
+        query!(
+            "
+            INSERT INTO audit
+            (username, time, action, success, addr, user_agent)
+            VALUES
+            (?, ?, ?, ?, ?, ?)
+            ",
+            event.username,
+            event.time,
+            event.action,
+            event.success,
+            event.addr,
+            event.user_agent,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error inserting audit event: {e:?}");
+        })
+    }
+
+    /// Events after `since`, oldest first, for one user or everyone
+    pub async fn audit_events(
+        &self,
+        username: Option<&str>,
+        since: Timestamp,
+    ) -> Result<Vec<AuditEvent>> {
+        query_as!(
+            AuditEvent,
+            r#"
+            SELECT username,
+                time as "time: _",
+                action as "action: _",
+                success as "success: bool",
+                addr, user_agent
+            FROM audit
+            WHERE (? IS NULL OR username = ?)
+            AND time > ?
+            ORDER BY time, rowid
+            "#,
+            username,
+            username,
+            since,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting audit events: {e:?}");
+        })
+    }
+}
+
+impl Backend {
+    pub async fn create_invite(&self, invite: &Invite) -> Result<()> {
+        query!(
+            "
+            INSERT INTO invites
+            (code, uses_left, created)
+            VALUES
+            (?, ?, ?)
+            ",
+            invite.code,
+            invite.uses_left,
+            invite.created,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error inserting invite: {e:?}");
+        })
+    }
+
+    pub async fn find_invite(&self, code: &str) -> std::result::Result<Invite, FindError> {
+        query_as!(
+            Invite,
+            r#"
+            SELECT code,
+                uses_left as "uses_left: u32",
+                created as "created: _"
+            FROM invites
+            WHERE code = ?
+            "#,
+            code,
+        )
+        .fetch_one(&self.0)
+        .await
+        .map_err(|e| {
+            if matches!(e, sqlx::Error::RowNotFound) {
+                FindError::NotFound
+            } else {
+                error!("couldn't query for invite: {e:?}");
+                FindError::Internal
+            }
+        })
+    }
+
+    pub async fn invites(&self) -> Result<Vec<Invite>> {
+        query_as!(
+            Invite,
+            r#"
+            SELECT code,
+                uses_left as "uses_left: u32",
+                created as "created: _"
+            FROM invites
+            ORDER BY created
+            "#,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting invites: {e:?}");
+        })
+    }
+
+    /// Use up one of an invite's uses, removing it once they're all gone
+    pub async fn use_invite(&self, code: &str) -> std::result::Result<(), FindError> {
+        let found = self
+            .transact(|mut tx| async {
+                let result = query!(
+                    "
+                    UPDATE invites
+                    SET uses_left = uses_left - 1
+                    WHERE code = ? AND uses_left > 0
+                    ",
+                    code,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error using invite: {e:?}");
+                })?;
+
+                query!(
+                    "
+                    DELETE FROM invites
+                    WHERE code = ? AND uses_left <= 0
+                    ",
+                    code,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error removing used invite: {e:?}");
+                })?;
+
+                Ok((tx, result.rows_affected() > 0))
+            })
+            .await
+            .map_err(|()| FindError::Internal)?;
+
+        match found {
+            true => Ok(()),
+            false => Err(FindError::NotFound),
+        }
+    }
+
+    pub async fn delete_invite(&self, code: &str) -> std::result::Result<(), FindError> {
+        let result = query!(
+            "
+            DELETE FROM invites
+            WHERE code = ?
+            ",
+            code,
+        )
+        .execute(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error deleting invite: {e:?}");
+            FindError::Internal
+        })?;
+
+        match result.rows_affected() {
+            0 => Err(FindError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Backend {
+    pub async fn create_token(&self, token: &AppToken) -> std::result::Result<(), CreateError> {
+        query!(
+            "
+            INSERT INTO app_tokens
+            (username, name, token_hash, device, read_only, created, last_used)
+            VALUES
+            (?, ?, ?, ?, ?, ?, ?)
+            ",
+            token.username,
+            token.name,
+            token.token_hash,
+            token.device,
+            token.read_only,
+            token.created,
+            token.last_used,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => {
+                CreateError::AlreadyExists
+            }
+            e => {
+                error!("error inserting token: {e:?}");
+                CreateError::Internal
+            }
+        })
+    }
+
+    pub async fn find_token(&self, token_hash: &str) -> std::result::Result<AppToken, FindError> {
+        query_as!(
+            AppToken,
+            r#"
+            SELECT username, name, token_hash, device,
+                read_only as "read_only: bool",
+                created as "created: _",
+                last_used as "last_used: _"
+            FROM app_tokens
+            WHERE token_hash = ?
+            "#,
+            token_hash,
+        )
+        .fetch_one(&self.0)
+        .await
+        .map_err(|e| {
+            if matches!(e, sqlx::Error::RowNotFound) {
+                FindError::NotFound
+            } else {
+                error!("couldn't query for token: {e:?}");
+                FindError::Internal
+            }
+        })
+    }
+
+    pub async fn tokens_for_user(&self, username: &str) -> Result<Vec<AppToken>> {
+        query_as!(
+            AppToken,
+            r#"
+            SELECT username, name, token_hash, device,
+                read_only as "read_only: bool",
+                created as "created: _",
+                last_used as "last_used: _"
+            FROM app_tokens
+            WHERE username = ?
+            ORDER BY name
+            "#,
+            username,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting tokens: {e:?}");
+        })
+    }
+
+    pub async fn touch_token(&self, token_hash: &str, now: Timestamp) -> Result<()> {
+        query!(
+            "
+            UPDATE app_tokens
+            SET last_used = ?
+            WHERE token_hash = ?
+            ",
+            now,
+            token_hash,
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error updating token: {e:?}");
+        })
+    }
+
+    /// Revoke a token, ending any sessions created with it
+    pub async fn delete_token(
+        &self,
+        username: &str,
+        name: &str,
+    ) -> std::result::Result<(), FindError> {
+        let found = self
+            .transact(|mut tx| async {
+                query!(
+                    "
+                    DELETE FROM sessions
+                    WHERE app_token IN (
+                        SELECT token_hash
+                        FROM app_tokens
+                        WHERE username = ? AND name = ?
+                    )
+                    ",
+                    username,
+                    name,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting token's sessions: {e:?}");
+                })?;
+
+                let result = query!(
+                    "
+                    DELETE FROM app_tokens
+                    WHERE username = ? AND name = ?
+                    ",
+                    username,
+                    name,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting token: {e:?}");
+                })?;
+
+                Ok((tx, result.rows_affected() > 0))
+            })
+            .await
+            .map_err(|()| FindError::Internal)?;
+
+        match found {
+            true => Ok(()),
+            false => Err(FindError::NotFound),
+        }
+    }
+}
+
+impl Backend {
+    pub async fn devices_for_user(&self, username: &str) -> Result<Vec<DeviceAndSub>> {
+        let rows = query!(
+            r#"
+            SELECT id, caption as "caption!: String", type as "type!: DeviceType",
+                (
+                    SELECT COUNT(*)
+                    FROM subscriptions
+                    WHERE subscriptions.username = devices.username
+                        AND subscriptions.device = devices.id
+                        AND deleted IS NULL
+                ) as "subscriptions!: u32",
+                last_seen as "last_seen: Timestamp",
+                user_agent, addr
+            FROM devices
+            LEFT JOIN device_activity
+                ON device_activity.username = devices.username
+                AND device_activity.device = devices.id
+            WHERE devices.username = ?
+            ORDER BY id
+            "#,
+            username,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting devices: {:?}", e);
+        })?;
+
+        let calls = query!(
+            r#"
+            SELECT device, api, last_called as "last_called: Timestamp"
+            FROM device_calls
+            WHERE username = ?
+            "#,
+            username,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting device calls: {e:?}");
+        })?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| DeviceAndSub {
+                activity: DeviceActivity {
+                    last_seen: row.last_seen,
+                    user_agent: row.user_agent,
+                    addr: row.addr,
+                    api_calls: calls
+                        .iter()
+                        .filter(|call| call.device == row.id)
+                        .map(|call| (call.api.clone(), call.last_called))
+                        .collect(),
+                },
+                id: row.id,
+                caption: row.caption,
+                r#type: row.r#type,
+                subscriptions: row.subscriptions,
+            })
+            .collect())
+    }
+
+    pub async fn update_device(
+        &self,
+        username: &str,
+        device_id: &str,
+        update: DeviceUpdate,
+    ) -> Result<()> {
+        let caption = update.caption;
+        let r#type = update.r#type;
+        let type_default = r#type.clone().unwrap_or_default();
+
+        query!(
+            "
+            INSERT INTO devices
+            (id, username, caption, type)
+            VALUES
+            (?, ?, ?, ?)
+            ON CONFLICT
+            DO
+                UPDATE SET
+                    caption = coalesce(?, devices.caption),
+                    type = coalesce(?, devices.type)
+                WHERE id = ? AND username = ?
+            ",
+            device_id,
+            username,
+            caption,
+            type_default,
+            caption,
+            r#type,
+            device_id,
+            username
+        )
+        .execute(&self.0)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            error!("error inserting device: {:?}", e);
+        })
+    }
+
+    /// Record that a device was just seen, calling `call` - unless it isn't registered
+    pub async fn touch_device(
+        &self,
+        username: &str,
+        device_id: &str,
+        call: ApiCall,
+        now: Timestamp,
+        user_agent: Option<&str>,
+        addr: Option<&str>,
+    ) -> Result<()> {
+        let api = call.as_str();
+
+        self.transact(|mut tx| async {
+            query!(
+                "
+                INSERT INTO device_activity
+                (username, device, last_seen, user_agent, addr)
+                SELECT ?, ?, ?, ?, ?
+                WHERE EXISTS (SELECT 1 FROM devices WHERE username = ? AND id = ?)
+                ON CONFLICT
+                DO
+                    UPDATE SET
+                        last_seen = ?,
+                        user_agent = coalesce(?, device_activity.user_agent),
+                        addr = coalesce(?, device_activity.addr)
+                ",
+                username,
+                device_id,
+                now,
+                user_agent,
+                addr,
+                username,
+                device_id,
+                now,
+                user_agent,
+                addr,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error updating device activity: {e:?}");
+            })?;
+
+            query!(
+                "
+                INSERT INTO device_calls
+                (username, device, api, last_called)
+                SELECT ?, ?, ?, ?
+                WHERE EXISTS (SELECT 1 FROM devices WHERE username = ? AND id = ?)
+                ON CONFLICT
+                DO
+                    UPDATE SET last_called = ?
+                ",
+                username,
+                device_id,
+                api,
+                now,
+                username,
+                device_id,
+                now,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error updating device calls: {e:?}");
+            })?;
+
+            Ok((tx, ()))
+        })
+        .await
+    }
+
+    /// Remove a device, its place in any sync group and its sessions, optionally
+    /// marking its subscriptions deleted at `now`
+    pub async fn retire_device(
+        &self,
+        username: &str,
+        device_id: &str,
+        now: Timestamp,
+        remove_subscriptions: bool,
+    ) -> std::result::Result<(), FindError> {
+        let found = self
+            .transact(|mut tx| async {
+                let result = query!(
+                    "DELETE FROM devices WHERE username = ? AND id = ?",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting device: {e:?}");
+                })?;
+                let found = result.rows_affected() > 0;
+
+                query!(
+                    "DELETE FROM sync_devices WHERE username = ? AND device = ?",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error removing device from sync group: {e:?}");
+                })?;
+                // a group of one syncs with nothing
+                query!(
+                    "
+                    DELETE FROM sync_devices
+                    WHERE username = ?
+                        AND sync_group IN (
+                            SELECT sync_group
+                            FROM sync_devices
+                            WHERE username = ?
+                            GROUP BY sync_group
+                            HAVING COUNT(*) < 2
+                        )
+                    ",
+                    username,
+                    username,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error dissolving sync groups: {e:?}");
+                })?;
+
+                query!(
+                    "DELETE FROM sessions WHERE username = ? AND device = ?",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting device's sessions: {e:?}");
+                })?;
+                query!(
+                    "
+                    DELETE FROM sessions
+                    WHERE app_token IN (
+                        SELECT token_hash
+                        FROM app_tokens
+                        WHERE username = ? AND device = ?
+                    )
+                    ",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting device's token sessions: {e:?}");
+                })?;
+                query!(
+                    "DELETE FROM app_tokens WHERE username = ? AND device = ?",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error revoking device's tokens: {e:?}");
+                })?;
+                query!(
+                    "DELETE FROM device_activity WHERE username = ? AND device = ?",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting device's activity: {e:?}");
+                })?;
+                query!(
+                    "DELETE FROM device_calls WHERE username = ? AND device = ?",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting device's calls: {e:?}");
+                })?;
+                query!(
+                    "
+                    DELETE FROM settings
+                    WHERE username = ? AND scope = 'device' AND target = ?
+                    ",
+                    username,
+                    device_id,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting device's settings: {e:?}");
+                })?;
+
+                if remove_subscriptions {
+                    query!(
+                        "
+                        UPDATE subscriptions
+                        SET deleted = ?
+                        WHERE username = ?
+                            AND device = ?
+                            AND deleted IS NULL
+                        ",
+                        now,
+                        username,
+                        device_id,
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error deleting device's subscriptions: {e:?}");
+                    })?;
+                }
+
+                Ok((tx, found))
+            })
+            .await
+            .map_err(|()| FindError::Internal)?;
+
+        match found {
+            true => Ok(()),
+            false => Err(FindError::NotFound),
+        }
+    }
+}
+
+impl Backend {
+    /// Groups of devices which share their subscriptions
+    pub async fn sync_groups(&self, username: &str) -> Result<Vec<Vec<String>>> {
+        let rows = query!(
+            "
+            SELECT device, sync_group
+            FROM sync_devices
+            WHERE username = ?
+            ORDER BY sync_group, device
+            ",
+            username,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting sync groups: {e:?}");
+        })?;
+
+        let mut groups: Vec<(i64, Vec<String>)> = vec![];
+        for row in rows {
+            match groups.last_mut() {
+                Some((group, devices)) if *group == row.sync_group => devices.push(row.device),
+                _ => groups.push((row.sync_group, vec![row.device])),
+            }
+        }
+
+        Ok(groups.into_iter().map(|(_, devices)| devices).collect())
+    }
+
+    pub async fn set_sync_groups(&self, username: &str, groups: &[Vec<String>]) -> Result<()> {
+        self.transact(|mut tx| async {
+            query!("DELETE FROM sync_devices WHERE username = ?", username)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error clearing sync groups: {e:?}");
+                })?;
+
+            for (group, devices) in groups.iter().enumerate() {
+                let group = group as i64;
+
+                for device in devices {
+                    query!(
+                        "
+                        INSERT INTO sync_devices
+                        (username, device, sync_group)
+                        VALUES
+                        (?, ?, ?)
+                        ",
+                        username,
+                        device,
+                        group,
+                    )
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| {
+                        error!("error inserting sync group: {e:?}");
+                    })?;
+                }
+            }
+
+            Ok((tx, ()))
+        })
+        .await
+    }
+}
+
+impl Backend {
+    pub async fn settings(&self, username: &str, target: &SettingsTarget) -> Result<Settings> {
+        let (scope, target, episode) = target.key();
+
+        let rows = query!(
+            "
+            SELECT key, value
+            FROM settings
+            WHERE username = ? AND scope = ? AND target = ? AND episode = ?
+            ORDER BY key
+            ",
+            username,
+            scope,
+            target,
+            episode,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting settings: {e:?}");
+        })?;
+
+        rows.into_iter()
+            .map(|row| {
+                let value = serde_json::from_str(&row.value).map_err(|e| {
+                    error!("invalid json in setting {:?}: {e:?}", row.key);
+                })?;
+                Ok((row.key, value))
+            })
+            .collect()
+    }
+
+    /// (username, podcast, value) for every user's podcast-scope `key` setting
+    pub async fn podcast_setting_values(
+        &self,
+        key: &str,
+    ) -> Result<Vec<(String, String, serde_json::Value)>> {
+        let rows = query!(
+            "
+            SELECT username, target, value
+            FROM settings
+            WHERE scope = 'podcast' AND key = ?
+            ",
+            key,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting podcast settings: {e:?}");
+        })?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| match serde_json::from_str(&row.value) {
+                Ok(value) => Some((row.username, row.target, value)),
+                Err(e) => {
+                    error!("invalid json in setting {key:?}: {e:?}");
+                    None
+                }
+            })
+            .collect())
+    }
+
+    /// (podcast, episode) pairs marked as favourites
+    pub async fn favorites(&self, username: &str) -> Result<Vec<(String, String)>> {
+        query!(
+            "
+            SELECT target, episode
+            FROM settings
+            WHERE username = ? AND scope = 'episode' AND key = ? AND value = 'true'
+            ORDER BY target, episode
+            ",
+            username,
+            FAVORITE,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| (row.target, row.episode))
+                .collect()
+        })
+        .map_err(|e| {
+            error!("error selecting favorites: {e:?}");
+        })
+    }
+
+    pub async fn update_settings(
+        &self,
+        username: &str,
+        target: &SettingsTarget,
+        update: &SettingsUpdate,
+    ) -> Result<()> {
+        let (scope, target, episode) = target.key();
+
+        self.transact(|mut tx| async {
+            for key in &update.remove {
+                query!(
+                    "
+                    DELETE FROM settings
+                    WHERE username = ? AND scope = ? AND target = ? AND episode = ? AND key = ?
+                    ",
+                    username,
+                    scope,
+                    target,
+                    episode,
+                    key,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error deleting setting: {e:?}");
+                })?;
+            }
+
+            for (key, value) in &update.set {
+                let value = value.to_string();
+                query!(
+                    "
+                    INSERT INTO settings
+                    (username, scope, target, episode, key, value)
+                    VALUES
+                    (?, ?, ?, ?, ?, ?)
+                    ON CONFLICT (username, scope, target, episode, key)
+                    DO UPDATE SET value = excluded.value
+                    ",
+                    username,
+                    scope,
+                    target,
+                    episode,
+                    key,
+                    value,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error setting setting: {e:?}");
+                })?;
+            }
+
+            Ok((tx, ()))
+        })
+        .await
+    }
+}
+
+impl Backend {
+    pub async fn podcast_lists(&self, username: &str) -> Result<Vec<ListInfo>> {
+        query_as!(
+            ListInfo,
+            r#"
+            SELECT name, title, public as "public: bool"
+            FROM podcast_lists
+            WHERE username = ?
+            ORDER BY name
+            "#,
+            username,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map_err(|e| {
+            error!("error selecting podcast lists: {e:?}");
+        })
+    }
+
+    pub async fn podcast_list(
+        &self,
+        username: &str,
+        name: &str,
+    ) -> std::result::Result<PodcastList, FindError> {
+        let info = query_as!(
+            ListInfo,
+            r#"
+            SELECT name, title, public as "public: bool"
+            FROM podcast_lists
+            WHERE username = ? AND name = ?
+            "#,
             username,
+            name,
         )
         .fetch_one(&self.0)
         .await
@@ -98,112 +1420,211 @@ impl Backend {
             if matches!(e, sqlx::Error::RowNotFound) {
                 FindError::NotFound
             } else {
+                error!("couldn't query for podcast list: {e:?}");
                 FindError::Internal
             }
-        })
-    }
+        })?;
 
-    /// session_id: set to None to logout / make NULL
-    pub async fn update_user(&self, username: &str, session_id: Option<&str>) -> bool {
-        query!(
+        let urls = query!(
             "
-            UPDATE users
-            SET session_id = ?
-            WHERE username = ?
+            SELECT url
+            FROM podcast_list_entries
+            WHERE username = ? AND list = ?
+            ORDER BY position
             ",
-            session_id,
             username,
+            name,
         )
-        .execute(&self.0)
+        .fetch_all(&self.0)
         .await
         .map_err(|e| {
-            error!("update user: {e}");
-            e
+            error!("error selecting podcast list entries: {e:?}");
+            FindError::Internal
+        })?;
+
+        Ok(PodcastList {
+            info,
+            urls: urls.into_iter().map(|row| row.url).collect(),
         })
-        .is_ok()
     }
 
-    pub async fn users_with_session(&self, session_id: &str) -> Result<Vec<User>> {
-        query_as!(
-            User,
-            "
-            SELECT *
-            FROM users
-            WHERE session_id = ?
-            ",
-            session_id,
-        )
-        .fetch_all(&self.0)
-        .await
-        .map_err(|e| {
-            error!("couldn't query for session {session_id}: {e:?}");
-        })
+    async fn insert_list_entries(
+        tx: &mut Transaction<'_, Sqlite>,
+        username: &str,
+        list: &PodcastList,
+    ) -> Result<()> {
+        for (position, url) in list.urls.iter().enumerate() {
+            let position = position as i64;
+            query!(
+                "
+                INSERT INTO podcast_list_entries
+                (username, list, position, url)
+                VALUES
+                (?, ?, ?, ?)
+                ",
+                username,
+                list.info.name,
+                position,
+                url,
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| {
+                error!("error inserting podcast list entry: {e:?}");
+            })?;
+        }
+
+        Ok(())
     }
-}
 
-impl Backend {
-    pub async fn devices_for_user(&self, username: &str) -> Result<Vec<DeviceAndSub>> {
-        query_as!(
-            DeviceAndSub,
-            r#"
-            SELECT id, caption as "caption!: _", type as "type!: _", COUNT(*) as "subscriptions!: _"
-            FROM devices
-            INNER JOIN subscriptions
-                ON devices.username = subscriptions.username
-            GROUP BY devices.username, devices.id
-            HAVING devices.username = ?
-            "#,
-            username,
-        )
-        .fetch_all(&self.0)
+    pub async fn create_podcast_list(
+        &self,
+        username: &str,
+        list: &PodcastList,
+    ) -> std::result::Result<(), CreateError> {
+        self.transact(|mut tx| async {
+            let inserted = query!(
+                "
+                INSERT INTO podcast_lists
+                (username, name, title, public)
+                VALUES
+                (?, ?, ?, ?)
+                ",
+                username,
+                list.info.name,
+                list.info.title,
+                list.info.public,
+            )
+            .execute(&mut *tx)
+            .await;
+
+            match inserted {
+                Ok(_) => {}
+                Err(sqlx::Error::Database(ref db_err)) if db_err.is_unique_violation() => {
+                    return Ok((tx, Err(CreateError::AlreadyExists)));
+                }
+                Err(e) => {
+                    error!("error inserting podcast list: {e:?}");
+                    return Err(());
+                }
+            }
+
+            Self::insert_list_entries(&mut tx, username, list).await?;
+
+            Ok((tx, Ok(())))
+        })
         .await
-        .map_err(|e| {
-            error!("error selecting devices: {:?}", e);
+        .map_err(|()| CreateError::Internal)?
+    }
+
+    /// Replace an existing list's title, visibility & URLs
+    pub async fn update_podcast_list(
+        &self,
+        username: &str,
+        list: &PodcastList,
+    ) -> std::result::Result<(), FindError> {
+        self.transact(|mut tx| async {
+            let updated = query!(
+                "
+                UPDATE podcast_lists
+                SET title = ?, public = ?
+                WHERE username = ? AND name = ?
+                ",
+                list.info.title,
+                list.info.public,
+                username,
+                list.info.name,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error updating podcast list: {e:?}");
+            })?;
+
+            if updated.rows_affected() == 0 {
+                return Ok((tx, Err(FindError::NotFound)));
+            }
+
+            query!(
+                "DELETE FROM podcast_list_entries WHERE username = ? AND list = ?",
+                username,
+                list.info.name,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error clearing podcast list entries: {e:?}");
+            })?;
+
+            Self::insert_list_entries(&mut tx, username, list).await?;
+
+            Ok((tx, Ok(())))
         })
+        .await
+        .map_err(|()| FindError::Internal)?
     }
 
-    pub async fn update_device(
+    pub async fn delete_podcast_list(
         &self,
         username: &str,
-        device_id: &str,
-        update: DeviceUpdate,
-    ) -> Result<()> {
-        let caption = update.caption;
-        let r#type = update.r#type;
-        let type_default = r#type.clone().unwrap_or_default();
+        name: &str,
+    ) -> std::result::Result<(), FindError> {
+        self.transact(|mut tx| async {
+            let deleted = query!(
+                "DELETE FROM podcast_lists WHERE username = ? AND name = ?",
+                username,
+                name,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error deleting podcast list: {e:?}");
+            })?;
+
+            query!(
+                "DELETE FROM podcast_list_entries WHERE username = ? AND list = ?",
+                username,
+                name,
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error deleting podcast list entries: {e:?}");
+            })?;
+
+            match deleted.rows_affected() {
+                0 => Ok((tx, Err(FindError::NotFound))),
+                _ => Ok((tx, Ok(()))),
+            }
+        })
+        .await
+        .map_err(|()| FindError::Internal)?
+    }
+}
 
+impl Backend {
+    /// Every actively subscribed URL, with how many users subscribe to it
+    pub async fn podcast_subscribers(&self) -> Result<Vec<(String, u32)>> {
         query!(
-            "
-            INSERT INTO devices
-            (id, username, caption, type)
-            VALUES
-            (?, ?, ?, ?)
-            ON CONFLICT
-            DO
-                UPDATE SET
-                    caption = coalesce(?, devices.caption),
-                    type = coalesce(?, devices.type)
-                WHERE id = ? AND username = ?
-            ",
-            device_id,
-            username,
-            caption,
-            type_default,
-            caption,
-            r#type,
-            device_id,
-            username
+            r#"
+            SELECT url, COUNT(DISTINCT username) as "subscribers!: u32"
+            FROM subscriptions
+            WHERE deleted IS NULL
+            GROUP BY url
+            "#,
         )
-        .execute(&self.0)
+        .fetch_all(&self.0)
         .await
-        .map(|_| ())
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| (row.url, row.subscribers))
+                .collect()
+        })
         .map_err(|e| {
-            error!("error inserting device: {:?}", e);
+            error!("error counting podcast subscribers: {e:?}");
         })
     }
-}
 
-impl Backend {
     pub async fn subscriptions(
         &self,
         username: &str,
@@ -235,6 +1656,33 @@ impl Backend {
         })
     }
 
+    /// The URLs subscribed to now, on one device or any
+    pub async fn current_subscriptions(
+        &self,
+        username: &str,
+        device_id: Option<&str>,
+    ) -> Result<Vec<String>> {
+        query!(
+            "
+            SELECT DISTINCT url
+            FROM subscriptions
+            WHERE username = ?
+                AND (? IS NULL OR device = ?)
+                AND deleted IS NULL
+            ORDER BY url
+            ",
+            username,
+            device_id,
+            device_id,
+        )
+        .fetch_all(&self.0)
+        .await
+        .map(|rows| rows.into_iter().map(|r| r.url).collect())
+        .map_err(|e| {
+            error!("error selecting current subscriptions: {e:?}");
+        })
+    }
+
     pub async fn update_subscriptions(
         &self,
         username: &str,
@@ -307,8 +1755,39 @@ impl Backend {
         let since = query.since.unwrap_or_else(Timestamp::zero);
         let podcast_filter = &query.podcast;
         let device_filter = &query.device;
-        // query.aggregated: unique on (sub, episode)-tuple - always true with how we store
 
+        if !query.is_aggregated() {
+            return query_as!(
+                EpisodeRaw,
+                r#"
+                SELECT episode_history.podcast, episode,
+                    guid, episode_history.device,
+                    timestamp as "timestamp: _",
+                    action as "action!: _",
+                    started, position, total,
+                    modified as "modified?: _"
+                FROM
+                    episode_history,
+                    (SELECT ? as podcast, ? as device) as filter
+                WHERE username = ?
+                    AND modified > ?
+                    AND (filter.podcast IS NULL OR filter.podcast = episode_history.podcast)
+                    AND (filter.device IS NULL OR filter.device = episode_history.device)
+                ORDER BY modified, episode_history.rowid
+                "#,
+                podcast_filter,
+                device_filter,
+                username,
+                since,
+            )
+            .fetch_all(&self.0)
+            .await
+            .map_err(|e| {
+                error!("error selecting episode history: {e:?}");
+            });
+        }
+
+        // `episodes` is unique on (podcast, episode), so is already aggregated
         query_as!(
             EpisodeRaw,
             r#"
@@ -394,6 +1873,9 @@ impl Backend {
                             content_hash = ?
                         -- only update if we've changed the contents
                         WHERE content_hash <> ?
+                        -- and never with an action older than the stored one, or
+                        -- without a timestamp to show it isn't
+                        AND (episodes.timestamp IS NULL OR ? >= episodes.timestamp)
                     ",
                     // values
                     username,
@@ -418,14 +1900,262 @@ impl Backend {
                     hash,
                     // update where
                     hash,
+                    timestamp,
                 )
                 .execute(&mut *tx)
                 .await
                 .map_err(|e| {
                     error!("error querying mid-transaction: {:?}", e);
                 })?;
+
+                query!(
+                    "
+                    INSERT INTO episode_history
+                    (
+                        username, device,
+                        podcast, episode,
+                        timestamp, guid,
+                        action,
+                        started, position, total,
+                        modified, content_hash
+                    )
+                    VALUES
+                    (
+                        ?, ?,
+                        ?, ?,
+                        ?, ?,
+                        ?,
+                        ?, ?, ?,
+                        ?, ?
+                    )
+                    ON CONFLICT
+                    DO NOTHING
+                    ",
+                    username,
+                    device,
+                    podcast,
+                    episode,
+                    timestamp,
+                    guid,
+                    action,
+                    started,
+                    position,
+                    total,
+                    now,
+                    hash,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error recording episode history: {e:?}");
+                })?;
+            }
+
+            Ok((tx, ()))
+        })
+        .await
+    }
+}
+
+impl Backend {
+    /// Rewrite subscription & episode URLs stored before they were sanitised, merging
+    /// any which now duplicate each other. Only runs once, recorded in the database's
+    /// `user_version` (as `SANITISED_URLS_VERSION`).
+    pub async fn sanitize_stored_urls(&self) -> Result<()> {
+        self.transact(|mut tx| async {
+            let version: i64 = sqlx::query_scalar("PRAGMA user_version")
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error checking for url sanitisation: {e:?}");
+                })?;
+            if version >= SANITISED_URLS_VERSION {
+                return Ok((tx, ()));
+            }
+
+            let subs = query!(
+                r#"
+                SELECT rowid as "rowid!: i64", username, device, url, deleted as "deleted: Timestamp"
+                FROM subscriptions
+                "#
+            )
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error selecting subscriptions to sanitise: {e:?}");
+            })?;
+
+            let mut rewritten = 0;
+            for sub in subs {
+                let Some(url) = sanitize(&sub.url).filter(|url| *url != sub.url) else {
+                    continue;
+                };
+                rewritten += 1;
+
+                // already subscribed under the sanitised URL: keep that one
+                let duplicate = query!(
+                    "
+                    SELECT rowid
+                    FROM subscriptions
+                    WHERE username = ? AND device = ? AND url = ? AND deleted IS ?
+                    ",
+                    sub.username,
+                    sub.device,
+                    url,
+                    sub.deleted,
+                )
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error finding duplicate subscription: {e:?}");
+                })?
+                .is_some();
+
+                let result = match duplicate {
+                    true => {
+                        query!("DELETE FROM subscriptions WHERE rowid = ?", sub.rowid)
+                            .execute(&mut *tx)
+                            .await
+                    }
+                    false => {
+                        query!(
+                            "UPDATE subscriptions SET url = ? WHERE rowid = ?",
+                            url,
+                            sub.rowid
+                        )
+                        .execute(&mut *tx)
+                        .await
+                    }
+                };
+                result.map_err(|e| {
+                    error!("error sanitising subscription: {e:?}");
+                })?;
+            }
+
+            let eps = query!(
+                r#"
+                SELECT rowid as "rowid!: i64", username, podcast, episode,
+                    timestamp as "timestamp: Time"
+                FROM episodes
+                "#
+            )
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error selecting episodes to sanitise: {e:?}");
+            })?;
+
+            for ep in eps {
+                let podcast = sanitize(&ep.podcast).unwrap_or_else(|| ep.podcast.clone());
+                let episode = sanitize(&ep.episode).unwrap_or_else(|| ep.episode.clone());
+                if podcast == ep.podcast && episode == ep.episode {
+                    continue;
+                }
+                rewritten += 1;
+
+                let existing = query!(
+                    r#"
+                    SELECT rowid as "rowid!: i64", timestamp as "timestamp: Time"
+                    FROM episodes
+                    WHERE username = ? AND podcast = ? AND episode = ?
+                    "#,
+                    ep.username,
+                    podcast,
+                    episode,
+                )
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error finding duplicate episode: {e:?}");
+                })?;
+
+                // of two spellings of an episode, keep the newer action
+                if let Some(existing) = existing {
+                    let stale = match (&ep.timestamp, &existing.timestamp) {
+                        (Some(ours), Some(theirs)) => ours <= theirs,
+                        (None, _) => true,
+                        (Some(_), None) => false,
+                    };
+                    let drop = if stale { ep.rowid } else { existing.rowid };
+
+                    query!("DELETE FROM episodes WHERE rowid = ?", drop)
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| {
+                            error!("error merging episode: {e:?}");
+                        })?;
+                    if stale {
+                        continue;
+                    }
+                }
+
+                query!(
+                    "UPDATE episodes SET podcast = ?, episode = ? WHERE rowid = ?",
+                    podcast,
+                    episode,
+                    ep.rowid,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error sanitising episode: {e:?}");
+                })?;
+            }
+
+            let history = query!(
+                r#"
+                SELECT rowid as "rowid!: i64", podcast, episode
+                FROM episode_history
+                "#
+            )
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| {
+                error!("error selecting episode history to sanitise: {e:?}");
+            })?;
+
+            for ep in history {
+                let podcast = sanitize(&ep.podcast).unwrap_or_else(|| ep.podcast.clone());
+                let episode = sanitize(&ep.episode).unwrap_or_else(|| ep.episode.clone());
+                if podcast == ep.podcast && episode == ep.episode {
+                    continue;
+                }
+                rewritten += 1;
+
+                // a duplicate action is already recorded under the sanitised URLs
+                query!(
+                    "UPDATE OR IGNORE episode_history SET podcast = ?, episode = ? WHERE rowid = ?",
+                    podcast,
+                    episode,
+                    ep.rowid,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error sanitising episode history: {e:?}");
+                })?;
+                query!(
+                    "DELETE FROM episode_history WHERE rowid = ? AND podcast = ? AND episode = ?",
+                    ep.rowid,
+                    ep.podcast,
+                    ep.episode,
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error merging episode history: {e:?}");
+                })?;
             }
 
+            // pragmas can't take parameters
+            sqlx::query(&format!("PRAGMA user_version = {SANITISED_URLS_VERSION}"))
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    error!("error completing url sanitisation: {e:?}");
+                })?;
+
+            info!("sanitised {rewritten} stored urls");
             Ok((tx, ()))
         })
         .await
@@ -0,0 +1,233 @@
+use crate::args::{Command, DeviceCommand, InviteCommand, UserCommand};
+use crate::auth::{check_new_password, pwhash};
+use crate::backend::{Backend, CreateError, FindError};
+use crate::invite::{self, Invite};
+use crate::time::Timestamp;
+use crate::user::valid_username;
+
+pub async fn run(command: &Command, backend: &Backend) -> Result<(), String> {
+    match command {
+        Command::User(cmd) => user(cmd, backend).await,
+        Command::Invite(cmd) => invite(cmd, backend).await,
+        Command::Device(cmd) => device(cmd, backend).await,
+    }
+}
+
+async fn user(command: &UserCommand, backend: &Backend) -> Result<(), String> {
+    match command {
+        UserCommand::Add { username } => {
+            if !valid_username(username) {
+                return Err(format!(
+                    "invalid username {username:?} (use letters, digits, '-', '_' or '@')"
+                ));
+            }
+
+            let pwhash = pwhash(&prompt_new_password(username)?);
+
+            backend
+                .create_user(username, &pwhash)
+                .await
+                .map_err(|e| match e {
+                    CreateError::AlreadyExists => format!("user {username:?} already exists"),
+                    CreateError::Internal => format!("couldn't create user {username:?}"),
+                })?;
+
+            println!("added {username}");
+        }
+        UserCommand::Passwd { username } => {
+            find_user(backend, username).await?;
+
+            let pwhash = pwhash(&prompt_new_password(username)?);
+
+            if !backend.update_pwhash(username, &pwhash).await {
+                return Err(format!("couldn't update {username:?}'s password"));
+            }
+
+            println!("updated {username}'s password");
+        }
+        UserCommand::Del { username } => {
+            backend
+                .delete_user(username)
+                .await
+                .map_err(|e| find_error(username, e))?;
+
+            println!("deleted {username}");
+        }
+        UserCommand::List => {
+            let users = backend
+                .users()
+                .await
+                .map_err(|()| "couldn't list users".to_string())?;
+
+            for user in users {
+                let flags = [(user.is_admin, "admin"), (user.disabled, "disabled")]
+                    .into_iter()
+                    .filter_map(|(set, flag)| set.then_some(flag))
+                    .collect::<Vec<_>>();
+
+                match flags.is_empty() {
+                    true => println!("{}", user.username),
+                    false => println!("{} ({})", user.username, flags.join(", ")),
+                }
+            }
+        }
+        UserCommand::Disable { username } => {
+            backend
+                .set_user_disabled(username, true)
+                .await
+                .map_err(|e| find_error(username, e))?;
+            backend
+                .delete_sessions_for_user(username, None)
+                .await
+                .map_err(|()| format!("couldn't end {username:?}'s sessions"))?;
+
+            println!("disabled {username}");
+        }
+        UserCommand::Enable { username } => {
+            backend
+                .set_user_disabled(username, false)
+                .await
+                .map_err(|e| find_error(username, e))?;
+
+            println!("enabled {username}");
+        }
+        UserCommand::Promote { username } => {
+            backend
+                .set_user_admin(username, true)
+                .await
+                .map_err(|e| find_error(username, e))?;
+
+            println!("{username} is now an admin");
+        }
+        UserCommand::Demote { username } => {
+            backend
+                .set_user_admin(username, false)
+                .await
+                .map_err(|e| find_error(username, e))?;
+
+            println!("{username} is no longer an admin");
+        }
+    }
+
+    Ok(())
+}
+
+async fn invite(command: &InviteCommand, backend: &Backend) -> Result<(), String> {
+    match command {
+        InviteCommand::Create { uses } => {
+            if *uses == 0 {
+                return Err("an invite needs at least one use".into());
+            }
+
+            let invite = Invite {
+                code: invite::generate(),
+                uses_left: *uses,
+                created: Timestamp::now().map_err(|e| format!("couldn't get time: {e:?}"))?,
+            };
+
+            backend
+                .create_invite(&invite)
+                .await
+                .map_err(|()| "couldn't create invite".to_string())?;
+
+            println!("{}", invite.code);
+        }
+        InviteCommand::List => {
+            let invites = backend
+                .invites()
+                .await
+                .map_err(|()| "couldn't list invites".to_string())?;
+
+            for invite in invites {
+                println!("{} ({} left)", invite.code, invite.uses_left);
+            }
+        }
+        InviteCommand::Revoke { code } => {
+            backend.delete_invite(code).await.map_err(|e| match e {
+                FindError::NotFound => format!("no such invite {code:?}"),
+                FindError::Internal => format!("couldn't revoke invite {code:?}"),
+            })?;
+
+            println!("revoked {code}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn device(command: &DeviceCommand, backend: &Backend) -> Result<(), String> {
+    match command {
+        DeviceCommand::List { username } => {
+            find_user(backend, username).await?;
+
+            let devices = backend
+                .devices_for_user(username)
+                .await
+                .map_err(|()| format!("couldn't list {username:?}'s devices"))?;
+
+            for dev in devices {
+                let seen = match dev.activity.last_seen {
+                    Some(seen) => format!("last seen {seen}"),
+                    None => "never seen".into(),
+                };
+                println!(
+                    "{} ({}, {} subscriptions, {seen}) {}",
+                    dev.id,
+                    dev.r#type.as_str(),
+                    dev.subscriptions,
+                    dev.caption,
+                );
+            }
+        }
+        DeviceCommand::Retire {
+            username,
+            device,
+            remove_subscriptions,
+        } => {
+            find_user(backend, username).await?;
+
+            let now = Timestamp::now().map_err(|e| format!("couldn't get time: {e:?}"))?;
+
+            backend
+                .retire_device(username, device, now, *remove_subscriptions)
+                .await
+                .map_err(|e| match e {
+                    FindError::NotFound => format!("{username:?} has no device {device:?}"),
+                    FindError::Internal => format!("couldn't retire {username:?}'s {device:?}"),
+                })?;
+
+            println!("retired {username}'s {device}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn find_user(backend: &Backend, username: &str) -> Result<(), String> {
+    backend
+        .find_user(username)
+        .await
+        .map(|_| ())
+        .map_err(|e| find_error(username, e))
+}
+
+fn find_error(username: &str, e: FindError) -> String {
+    match e {
+        FindError::NotFound => format!("no such user {username:?}"),
+        FindError::Internal => format!("couldn't access user {username:?}"),
+    }
+}
+
+fn prompt_new_password(username: &str) -> Result<String, String> {
+    let prompt = |p: &str| rpassword::prompt_password(p).map_err(|e| format!("{e}"));
+
+    let pass = prompt(&format!("{username}'s password: "))?;
+    check_new_password(username, &pass)?;
+
+    let confirm = prompt(&format!("{username}'s password (again): "))?;
+    if pass != confirm {
+        return Err("passwords don't match".into());
+    }
+
+    Ok(pass)
+}
@@ -1,12 +1,72 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+use crate::time::Timestamp;
+
 #[derive(Debug, Serialize)]
-#[cfg_attr(feature = "backend-sql", derive(sqlx::Type))]
 pub struct DeviceAndSub {
     pub id: String,
     pub caption: String,
     pub r#type: DeviceType,
     pub subscriptions: u32,
+    #[serde(flatten)]
+    pub activity: DeviceActivity,
+}
+
+/// What's been seen of a device as it syncs. Empty for devices not seen
+/// since this started being recorded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceActivity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<String>,
+    /// When each API was last called
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub api_calls: BTreeMap<String, Timestamp>,
+}
+
+/// The APIs recorded in a device's activity
+#[derive(Debug, Clone, Copy)]
+pub enum ApiCall {
+    Device,
+    Subscriptions,
+    Updates,
+    Episodes,
+}
+
+impl ApiCall {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Device => "device",
+            Self::Subscriptions => "subscriptions",
+            Self::Updates => "updates",
+            Self::Episodes => "episodes",
+        }
+    }
+}
+
+/// A device which hasn't synced recently, for the admin report
+#[derive(Debug, Serialize)]
+pub struct StaleDevice {
+    pub username: String,
+    #[serde(flatten)]
+    pub device: DeviceAndSub,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryStale {
+    /// How long a device must have gone without syncing
+    pub days: Option<u32>,
+}
+
+impl QueryStale {
+    pub fn days(&self) -> u32 {
+        self.days.unwrap_or(30)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -15,7 +75,62 @@ pub struct DeviceUpdate {
     pub r#type: Option<DeviceType>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize)]
+pub struct QueryRetire {
+    /// Mark the device's subscriptions deleted, so other devices hear of the removals
+    #[serde(default)]
+    pub remove_subscriptions: bool,
+}
+
+/// Which of a user's devices share their subscriptions
+#[derive(Debug, Serialize)]
+pub struct SyncStatus {
+    pub synchronized: Vec<Vec<String>>,
+    #[serde(rename = "not-synchronized")]
+    pub not_synchronized: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncRequest {
+    #[serde(default)]
+    pub synchronize: Vec<Vec<String>>,
+    #[serde(default, rename = "stop-synchronize")]
+    pub stop_synchronize: Vec<String>,
+}
+
+impl SyncRequest {
+    /// Apply to existing sync groups: each `synchronize` list joins any
+    /// groups its devices are already in, then `stop-synchronize`
+    /// devices leave theirs. Groups left with a single device dissolve.
+    pub fn apply(&self, mut groups: Vec<Vec<String>>) -> Vec<Vec<String>> {
+        for sync in &self.synchronize {
+            let (joined, mut rest): (Vec<_>, Vec<_>) = groups
+                .into_iter()
+                .partition(|group| group.iter().any(|dev| sync.contains(dev)));
+
+            let mut merged = joined
+                .into_iter()
+                .flatten()
+                .chain(sync.iter().cloned())
+                .collect::<Vec<_>>();
+            merged.sort();
+            merged.dedup();
+
+            rest.push(merged);
+            groups = rest;
+        }
+
+        for group in &mut groups {
+            group.retain(|dev| !self.stop_synchronize.contains(dev));
+        }
+        groups.retain(|group| group.len() > 1);
+        groups.sort();
+
+        groups
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[cfg_attr(feature = "backend-sql", derive(sqlx::Type))]
 #[serde(rename_all = "lowercase")]
 pub enum DeviceType {
@@ -23,11 +138,11 @@ pub enum DeviceType {
     Laptop,
     Mobile,
     Server,
+    #[default]
     Other, // aka null
 }
 
 impl DeviceType {
-    #[allow(dead_code)]
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::Desktop => "Desktop",
@@ -39,18 +154,16 @@ impl DeviceType {
     }
 }
 
-impl Default for DeviceType {
-    fn default() -> Self {
-        Self::Other
-    }
-}
-
 impl TryFrom<&'_ str> for DeviceType {
     type Error = ();
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
         match s {
+            "Desktop" => Ok(DeviceType::Desktop),
+            "Laptop" => Ok(DeviceType::Laptop),
             "Mobile" => Ok(DeviceType::Mobile),
+            "Server" => Ok(DeviceType::Server),
+            "Other" => Ok(DeviceType::Other),
             _ => Err(()),
         }
     }
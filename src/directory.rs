@@ -0,0 +1,125 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::podcast::Podcast;
+
+/// The podcast-scope setting users tag podcasts with: an array of strings
+pub const TAGS: &str = "tags";
+
+/// The most results a directory query returns
+pub const MAX_RESULTS: usize = 100;
+
+pub struct Config {
+    /// Podcasts with fewer subscribers aren't listed - private feeds often carry
+    /// a secret token in their URL
+    pub min_subscribers: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tag {
+    pub title: String,
+    pub tag: String,
+    /// How many podcasts carry the tag
+    pub usage: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuerySearch {
+    pub q: Option<String>,
+    pub jsonp: Option<String>,
+}
+
+/// Rank podcasts by subscriber count, most first, then by URL
+pub fn ranked(subscribers: Vec<(String, u32)>) -> Vec<Podcast> {
+    let mut podcasts = subscribers
+        .into_iter()
+        .map(|(url, count)| Podcast {
+            subscribers: count,
+            ..Podcast::from_url(url)
+        })
+        .collect::<Vec<_>>();
+
+    podcasts.sort_by(|a, b| {
+        b.subscribers
+            .cmp(&a.subscribers)
+            .then_with(|| a.url.cmp(&b.url))
+    });
+    podcasts
+}
+
+/// Each podcast's tags, from users' `tags` settings: (username, podcast, value).
+/// Tags are lowercased, so "Tech" and "tech" are the same tag.
+pub fn podcast_tags(values: Vec<(String, String, Value)>) -> HashMap<String, HashSet<String>> {
+    let mut tags = HashMap::<String, HashSet<String>>::new();
+
+    for (_, podcast, value) in values {
+        let Value::Array(values) = value else {
+            continue;
+        };
+
+        let podcast_tags = tags.entry(podcast).or_default();
+        for tag in values {
+            if let Value::String(tag) = tag {
+                let tag = tag.trim().to_lowercase();
+                if !tag.is_empty() {
+                    podcast_tags.insert(tag);
+                }
+            }
+        }
+    }
+
+    tags.retain(|_, tags| !tags.is_empty());
+    tags
+}
+
+/// The most used tags, most first
+pub fn top_tags(tags: &HashMap<String, HashSet<String>>, count: usize) -> Vec<Tag> {
+    let mut usage = HashMap::<&str, u32>::new();
+    for tag in tags.values().flatten() {
+        *usage.entry(tag).or_default() += 1;
+    }
+
+    let mut top = usage
+        .into_iter()
+        .map(|(tag, usage)| Tag {
+            title: tag.to_string(),
+            tag: tag.to_string(),
+            usage,
+        })
+        .collect::<Vec<_>>();
+    top.sort_by(|a, b| b.usage.cmp(&a.usage).then_with(|| a.tag.cmp(&b.tag)));
+    top.truncate(count);
+    top
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn tags() {
+        let tags = podcast_tags(vec![
+            (
+                "bob".into(),
+                "https://a.example".into(),
+                json!(["Tech", "news"]),
+            ),
+            ("amy".into(), "https://a.example".into(), json!(["tech "])),
+            ("amy".into(), "https://b.example".into(), json!(["tech"])),
+            ("amy".into(), "https://c.example".into(), json!("tech")),
+        ]);
+
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags["https://a.example"].len(), 2);
+
+        let top = top_tags(&tags, 10);
+        assert_eq!(
+            top.iter().map(|t| (&*t.tag, t.usage)).collect::<Vec<_>>(),
+            [("tech", 2), ("news", 1)]
+        );
+        assert_eq!(top_tags(&tags, 1).len(), 1);
+    }
+}
@@ -15,7 +15,7 @@ pub enum EpisodeAction {
     Delete,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[cfg_attr(feature = "backend-sql", derive(sqlx::Type))]
 #[serde(rename_all = "lowercase")]
 pub enum EpisodeActionRaw {
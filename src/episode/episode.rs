@@ -31,7 +31,7 @@ impl Episode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[serde_with::skip_serializing_none]
 #[derive(Deserialize, Serialize)] // transitive, from Episode
 #[cfg_attr(feature = "backend-sql", derive(sqlx::Type))]
@@ -75,7 +75,7 @@ impl EpisodeRaw {
         Self {
             podcast,
             episode,
-            timestamp: timestamp.map(Into::into),
+            timestamp,
             guid,
             action,
             started,
@@ -118,7 +118,7 @@ impl TryFrom<EpisodeRaw> for Episode {
         Ok(Self {
             podcast,
             episode,
-            timestamp: timestamp.map(Into::into),
+            timestamp,
             guid,
             action,
             device,
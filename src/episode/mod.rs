@@ -4,6 +4,7 @@ pub use action::{EpisodeAction, EpisodeActionRaw};
 mod episodes;
 pub use episodes::Episodes;
 
+#[allow(clippy::module_inception)]
 mod episode;
 pub use episode::{Episode, EpisodeRaw};
 
@@ -6,7 +6,7 @@ use time::{
 
 // this struct exists to work around #[serde(with = ...)]
 // not handling Option for us
-#[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
 #[serde(transparent)]
 #[cfg_attr(feature = "backend-sql", derive(sqlx::Type), sqlx(transparent))]
 pub struct Time(#[serde(with = "time_no_offset")] PrimitiveDateTime);
@@ -31,9 +31,9 @@ impl Time {
     }
 }
 
-impl Into<PrimitiveDateTime> for Time {
-    fn into(self) -> PrimitiveDateTime {
-        self.0
+impl From<Time> for PrimitiveDateTime {
+    fn from(time: Time) -> Self {
+        time.0
     }
 }
 
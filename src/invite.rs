@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::time::Timestamp;
+
+/// A code allowing someone to register an account
+#[derive(Debug, Serialize)]
+pub struct Invite {
+    pub code: String,
+    pub uses_left: u32,
+    pub created: Timestamp,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Registration {
+    pub invite: String,
+    pub password: String,
+}
+
+pub fn generate() -> String {
+    Uuid::new_v4().as_simple().to_string()
+}
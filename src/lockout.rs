@@ -0,0 +1,237 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::{error, warn};
+
+pub struct Config {
+    /// Failures allowed for a username before it's locked out
+    pub user_threshold: u32,
+    /// Failures allowed from an address before it's locked out
+    pub addr_threshold: u32,
+    /// The first lockout's length, doubling with each further failure
+    pub base: Duration,
+    /// The longest lockout, and how long failures are remembered for
+    pub max: Duration,
+}
+
+/// Tracks failed login attempts, locking out usernames and addresses
+/// which fail too often
+pub struct Lockout {
+    config: Config,
+    failures: Mutex<HashMap<Key, Failures>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    User(String),
+    Addr(IpAddr),
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            user_threshold: 5,
+            addr_threshold: 20,
+            base: Duration::from_secs(30),
+            max: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl Key {
+    pub fn for_attempt(username: Option<&str>, addr: Option<IpAddr>) -> Vec<Self> {
+        username
+            .map(|u| Self::User(u.into()))
+            .into_iter()
+            .chain(addr.map(Self::Addr))
+            .collect()
+    }
+
+    fn threshold(&self, config: &Config) -> u32 {
+        match self {
+            Self::User(_) => config.user_threshold,
+            Self::Addr(_) => config.addr_threshold,
+        }
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User(u) => write!(f, "user {u}"),
+            Self::Addr(a) => write!(f, "address {a}"),
+        }
+    }
+}
+
+impl Lockout {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            failures: Default::default(),
+        }
+    }
+
+    /// Returns how long until an attempt may be made, if any of `keys` are locked out
+    pub fn check(&self, keys: &[Key]) -> Result<(), Duration> {
+        self.check_at(keys, Instant::now())
+    }
+
+    pub fn record_failure(&self, keys: &[Key]) {
+        self.record_failure_at(keys, Instant::now())
+    }
+
+    pub fn record_success(&self, keys: &[Key]) {
+        let Some(mut failures) = self.lock() else {
+            return;
+        };
+
+        // an address may be shared (e.g. NAT), so one user's success doesn't clear it
+        for key in keys {
+            if matches!(key, Key::User(_)) {
+                failures.remove(key);
+            }
+        }
+    }
+
+    fn check_at(&self, keys: &[Key], now: Instant) -> Result<(), Duration> {
+        let Some(failures) = self.lock() else {
+            return Ok(());
+        };
+
+        let wait = keys
+            .iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .filter_map(|until| until.checked_duration_since(now))
+            .max();
+
+        match wait {
+            Some(wait) if !wait.is_zero() => Err(wait),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure_at(&self, keys: &[Key], now: Instant) {
+        let Some(mut failures) = self.lock() else {
+            return;
+        };
+
+        let max = self.config.max;
+        failures.retain(|_, f| now.duration_since(f.last) < max);
+
+        for key in keys {
+            let entry = failures.entry(key.clone()).or_insert(Failures {
+                count: 0,
+                last: now,
+                locked_until: None,
+            });
+            entry.count += 1;
+            entry.last = now;
+
+            let threshold = key.threshold(&self.config);
+            if entry.count < threshold {
+                continue;
+            }
+
+            let doublings = (entry.count - threshold).min(31);
+            let lockout = self
+                .config
+                .base
+                .saturating_mul(1 << doublings)
+                .min(self.config.max);
+            entry.locked_until = Some(now + lockout);
+
+            warn!(
+                "locking out {key} for {}s after {} failed logins",
+                lockout.as_secs(),
+                entry.count
+            );
+        }
+    }
+
+    fn lock(&self) -> Option<std::sync::MutexGuard<'_, HashMap<Key, Failures>>> {
+        self.failures
+            .lock()
+            .map_err(|e| {
+                error!("lockout state poisoned: {e}");
+            })
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let lockout = Lockout::new(Config {
+            user_threshold: 3,
+            addr_threshold: 5,
+            base: Duration::from_secs(10),
+            max: Duration::from_secs(100),
+        });
+        let bob = Key::for_attempt(Some("bob"), None);
+        let secs = Duration::from_secs;
+        let start = Instant::now();
+
+        // under the threshold, nothing's locked
+        lockout.record_failure_at(&bob, start);
+        lockout.record_failure_at(&bob, start);
+        assert_eq!(lockout.check_at(&bob, start), Ok(()));
+
+        // at the threshold, we're locked for the base time
+        lockout.record_failure_at(&bob, start);
+        assert_eq!(lockout.check_at(&bob, start), Err(secs(10)));
+        assert_eq!(lockout.check_at(&bob, start + secs(10)), Ok(()));
+
+        // then each further failure doubles it, up to the max
+        lockout.record_failure_at(&bob, start + secs(10));
+        assert_eq!(lockout.check_at(&bob, start + secs(10)), Err(secs(20)));
+        for _ in 0..5 {
+            lockout.record_failure_at(&bob, start + secs(30));
+        }
+        assert_eq!(lockout.check_at(&bob, start + secs(30)), Err(secs(100)));
+
+        // other users aren't affected, and success resets
+        let tim = Key::for_attempt(Some("tim"), None);
+        assert_eq!(lockout.check_at(&tim, start + secs(30)), Ok(()));
+
+        lockout.record_success(&bob);
+        assert_eq!(lockout.check_at(&bob, start + secs(30)), Ok(()));
+    }
+
+    #[test]
+    fn per_address() {
+        let lockout = Lockout::new(Config {
+            user_threshold: 3,
+            addr_threshold: 5,
+            base: Duration::from_secs(10),
+            max: Duration::from_secs(100),
+        });
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        // spraying different usernames from one address locks the address
+        for i in 0..5 {
+            let keys = Key::for_attempt(Some(&format!("user{i}")), Some(addr));
+            lockout.record_failure_at(&keys, start);
+        }
+
+        let keys = Key::for_attempt(Some("bob"), Some(addr));
+        assert!(lockout.check_at(&keys, start).is_err());
+
+        let keys = Key::for_attempt(Some("bob"), Some("192.0.2.2".parse().unwrap()));
+        assert_eq!(lockout.check_at(&keys, start), Ok(()));
+    }
+}
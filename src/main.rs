@@ -3,52 +3,95 @@
 
 use std::{net::SocketAddr, path::Path, sync::Arc};
 
-use ::time::ext::NumericalDuration;
 use axum::{
     extract::{ConnectInfo, Path as AxumPath, Query, Request, State},
-    http::{header, HeaderMap, HeaderValue, StatusCode},
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
     middleware::{self, Next},
-    response::{Json, Response},
-    routing::{get, post},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
     RequestExt as _, Router,
 };
 use cookie::{Cookie, SameSite};
 use log::{debug, error, info};
 use serde::Deserialize;
 
+mod audit;
+
 mod auth;
 use auth::{BasicAuth, SessionId};
 
 mod user;
 
+mod session;
+
+mod client;
+use client::ClientInfo;
+
+mod token;
+
 mod device;
 
 mod subscription;
 
 mod episode;
 
+mod podcast;
+
+mod directory;
+
+mod podcast_list;
+
+mod settings;
+
+mod updates;
+
+mod invite;
+
+mod lockout;
+
+mod proxy;
+
+mod url_list;
+
+mod sanitize;
+
 mod podsync;
-use podsync::{PodSync, PodSyncAuthed};
+use podsync::{PodSync, PodSyncAdmin, PodSyncAuthed};
 
 mod time;
 use crate::time::Timestamp;
 
 mod path_format;
-use path_format::split_format_json;
+use path_format::{split_format, split_format_json};
 
 mod args;
 use args::Args;
 
+mod cli;
+
 mod backend;
 use backend::Backend;
 
 static COOKIE_NAME: &str = "sessionid"; // gpodder/mygpo, doc/api/reference/auth.rst:16
 
+/// Nextcloud's gpoddersync has no devices, so its subscriptions live on this one
+static NEXTCLOUD_DEVICE: &str = "nextcloud";
+
 #[derive(Debug, Deserialize)]
 pub struct QuerySince {
     since: crate::time::Timestamp,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct QueryOptionalSince {
+    since: Option<crate::time::Timestamp>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryJsonp {
+    jsonp: Option<String>,
+}
+
 #[derive(Clone)]
 struct AppState {
     podsync: Arc<PodSync>,
@@ -67,10 +110,26 @@ async fn main() {
     }
     let data_dir = args.data_dir().unwrap_or_else(|| Path::new("."));
 
-    let backend = Backend::new(&data_dir).await;
+    let backend = Backend::new(data_dir).await;
+
+    if let Some(command) = args.command() {
+        if let Err(e) = cli::run(command, &backend).await {
+            eprintln!("podsync: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
     let secure = args.secure();
-    let podsync = Arc::new(PodSync::new(backend));
+    let config = podsync::Config {
+        session_lifetime: args.session_lifetime(),
+        session_idle_timeout: args.session_idle_timeout(),
+        lockout: args.lockout(),
+        registration: args.registration(),
+        proxy: args.proxy(),
+        directory: args.directory(),
+    };
+    let podsync = Arc::new(PodSync::new(backend, config));
 
     let app = routes(podsync, secure);
 
@@ -94,19 +153,111 @@ fn routes(podsync: Arc<PodSync>, secure: bool) -> Router {
         .route("/", get(hello))
         .route("/api/2/auth/:username/login.json", post(login))
         .route("/api/2/auth/:username/logout.json", post(logout))
+        .route("/api/2/auth/:username/logout-all.json", post(logout_all))
+        .route("/api/2/auth/:username/register.json", post(register))
+        .route("/api/2/auth/:username/password.json", post(change_password))
+        .route("/api/2/tokens/:username_format", get(get_tokens))
+        .route(
+            "/api/2/tokens/:username/:name_format",
+            post(create_token).delete(revoke_token),
+        )
         .route("/api/2/devices/:username_format", get(get_devices))
         .route(
             "/api/2/devices/:username/:device_format",
-            post(update_device),
+            post(update_device).delete(retire_device),
+        )
+        .route(
+            "/api/2/sync-devices/:username_format",
+            get(get_sync_devices).post(update_sync_devices),
         )
         .route(
             "/api/2/subscriptions/:username/:device_format",
             get(get_subscriptions).post(update_subscriptions),
         )
+        .route(
+            "/api/2/settings/:username/:scope_format",
+            get(get_settings).post(update_settings),
+        )
+        .route("/toplist/:count_format", get(get_toplist))
+        .route("/search.json", get(search))
+        .route("/search.jsonp", get(search))
+        .route("/search.opml", get(search))
+        .route("/search.txt", get(search))
+        .route("/search.xml", get(search))
+        .route("/api/2/tags/:count_format", get(get_top_tags))
+        .route("/api/2/tag/:tag/:count_format", get(get_tag_podcasts))
+        .route("/api/2/lists/:username_format", get(get_podcast_lists))
+        .route(
+            "/api/2/lists/:username/:create_format",
+            post(create_podcast_list),
+        )
+        .route(
+            "/api/2/lists/:username/list/:name_format",
+            get(get_podcast_list)
+                .put(update_podcast_list)
+                .delete(delete_podcast_list),
+        )
+        .route("/api/2/favorites/:username_format", get(get_favorites))
+        .route(
+            "/api/2/updates/:username/:device_format",
+            get(get_device_updates),
+        )
         .route(
             "/api/2/episodes/:username_format",
             get(get_episodes).post(update_episodes),
         )
+        .route("/api/2/audit/:username_format", get(get_audit))
+        .route(
+            "/subscriptions/:username_format",
+            get(get_all_subscriptions),
+        )
+        .route(
+            "/subscriptions/:username/:device_format",
+            get(get_device_subscriptions).put(replace_subscriptions),
+        )
+        .route(
+            "/index.php/apps/gpoddersync/subscriptions",
+            get(nextcloud_subscriptions),
+        )
+        .route(
+            "/index.php/apps/gpoddersync/subscription_change/create",
+            post(nextcloud_update_subscriptions),
+        )
+        .route(
+            "/index.php/apps/gpoddersync/episode_action",
+            get(nextcloud_episodes),
+        )
+        .route(
+            "/index.php/apps/gpoddersync/episode_action/create",
+            post(nextcloud_update_episodes),
+        )
+        .route("/api/admin/users.json", get(admin_users))
+        .route("/api/admin/audit.json", get(admin_audit))
+        .route("/api/admin/devices.json", get(admin_stale_devices))
+        .route(
+            "/api/admin/users/:username_format",
+            delete(admin_delete_user),
+        )
+        .route(
+            "/api/admin/users/:username/password.json",
+            post(admin_reset_password),
+        )
+        .route(
+            "/api/admin/users/:username/disable.json",
+            post(admin_disable_user),
+        )
+        .route(
+            "/api/admin/users/:username/enable.json",
+            post(admin_enable_user),
+        )
+        .route(
+            "/api/admin/users/:username/logout-all.json",
+            post(admin_revoke_sessions),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            session_renewal_middleware,
+        ))
         .layer(middleware::from_fn(log_middleware))
         .with_state(state)
 }
@@ -118,58 +269,148 @@ async fn hello() -> &'static str {
 async fn login(
     State(state): State<AppState>,
     AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
     headers: HeaderMap,
 ) -> Result<(HeaderMap, StatusCode), podsync::Error> {
-    let auth_header = headers.get(header::AUTHORIZATION).ok_or_else(|| {
-        error!("couldn't auth {:?} - no auth header/cookie", username);
-        podsync::Error::Unauthorized
-    })?;
+    let session_id = extract_session_id(&headers);
 
-    let auth_str = auth_header
-        .to_str()
-        .map_err(|_| podsync::Error::Unauthorized)?;
-    let auth: BasicAuth = auth_str.parse().map_err(|_| podsync::Error::Unauthorized)?;
-    let auth = auth.with_path_username(&username).map_err(|e| {
-        error!("{e}");
-        podsync::Error::Unauthorized
-    })?;
+    let authed = if let Some(proxy_user) = state.podsync.proxy_user(&headers, &client) {
+        check_proxy_user(proxy_user, &username)?;
+        state
+            .podsync
+            .login_proxy(proxy_user, session_id, &client)
+            .await?
+    } else {
+        let auth_header = headers.get(header::AUTHORIZATION).ok_or_else(|| {
+            error!("couldn't auth {:?} - no auth header/cookie", username);
+            podsync::Error::Unauthorized
+        })?;
+
+        let auth_str = auth_header
+            .to_str()
+            .map_err(|_| podsync::Error::Unauthorized)?;
+        let auth: BasicAuth = auth_str.parse().map_err(|_| podsync::Error::Unauthorized)?;
+        let auth = auth.with_path_username(&username).map_err(|e| {
+            error!("{e}");
+            podsync::Error::Unauthorized
+        })?;
+
+        state.podsync.login(auth, session_id, &client).await?
+    };
+    let session_id = authed.session_id().ok_or(podsync::Error::Internal)?;
 
-    let session_id = extract_session_id(&headers);
-    let authed = state.podsync.login(auth, session_id).await?;
-    let session_id = authed.session_id();
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::SET_COOKIE, session_cookie(&state, session_id)?);
+
+    Ok((response_headers, StatusCode::OK))
+}
+
+fn session_cookie(state: &AppState, session_id: &SessionId) -> podsync::Result<HeaderValue> {
+    let max_age = ::time::Duration::try_from(state.podsync.session_lifetime())
+        .map_err(|_| podsync::Error::Internal)?;
 
     let cookie = Cookie::build((COOKIE_NAME, session_id.to_string()))
         .secure(state.secure)
         .http_only(true)
         .same_site(SameSite::Strict)
-        .max_age(2.weeks())
+        .max_age(max_age)
         .path("/api");
 
-    let cookie_val =
-        HeaderValue::from_str(&cookie.to_string()).map_err(|_| podsync::Error::Internal)?;
-    let mut response_headers = HeaderMap::new();
-    response_headers.insert(header::SET_COOKIE, cookie_val);
-
-    Ok((response_headers, StatusCode::OK))
+    HeaderValue::from_str(&cookie.to_string()).map_err(|_| podsync::Error::Internal)
 }
 
 async fn logout(
     State(state): State<AppState>,
     AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    authed.logout(&client).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn logout_all(
+    State(state): State<AppState>,
+    AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    authed.logout_all(&client).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn change_password(
+    State(state): State<AppState>,
+    AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(change): Json<user::PasswordChange>,
+) -> Result<StatusCode, podsync::Error> {
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    authed.change_password(change, &client).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn register(
+    State(state): State<AppState>,
+    AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    Json(registration): Json<invite::Registration>,
+) -> Result<StatusCode, podsync::Error> {
+    state
+        .podsync
+        .register(&username, registration, &client)
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn get_tokens(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<Vec<token::AppToken>>, podsync::Error> {
+    let username = split_format_json(&username_format)?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
+    let tokens = authed.tokens().await?;
+    Ok(Json(tokens))
+}
+
+async fn create_token(
+    State(state): State<AppState>,
+    AxumPath((username, name_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(request): Json<token::TokenRequest>,
+) -> Result<Json<token::NewToken>, podsync::Error> {
+    let name = split_format_json(&name_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let token = authed.create_token(name, request).await?;
+    Ok(Json(token))
+}
+
+async fn revoke_token(
+    State(state): State<AppState>,
+    AxumPath((username, name_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
     headers: HeaderMap,
 ) -> Result<StatusCode, podsync::Error> {
-    let authed = authorize_request(&state.podsync, &username, &headers).await?;
-    authed.logout().await?;
+    let name = split_format_json(&name_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    authed.revoke_token(name).await?;
     Ok(StatusCode::OK)
 }
 
 async fn get_devices(
     State(state): State<AppState>,
     AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
     headers: HeaderMap,
 ) -> Result<Json<Vec<device::DeviceAndSub>>, podsync::Error> {
     let username = split_format_json(&username_format)?;
-    let authed = authorize_request(&state.podsync, username, &headers).await?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
     let devs = authed.devices().await?;
     Ok(Json(devs))
 }
@@ -177,47 +418,335 @@ async fn get_devices(
 async fn update_device(
     State(state): State<AppState>,
     AxumPath((username, device_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
     headers: HeaderMap,
     Json(device): Json<device::DeviceUpdate>,
 ) -> Result<StatusCode, podsync::Error> {
     let device_id = split_format_json(&device_format)?;
-    let authed = authorize_request(&state.podsync, &username, &headers).await?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
     authed.update_device(device_id, device).await?;
     Ok(StatusCode::OK)
 }
 
+async fn retire_device(
+    State(state): State<AppState>,
+    AxumPath((username, device_format)): AxumPath<(String, String)>,
+    Query(query): Query<device::QueryRetire>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let device_id = split_format_json(&device_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    authed.retire_device(device_id, query).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_sync_devices(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<device::SyncStatus>, podsync::Error> {
+    let username = split_format_json(&username_format)?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
+    let status = authed.sync_status().await?;
+    Ok(Json(status))
+}
+
+async fn update_sync_devices(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(req): Json<device::SyncRequest>,
+) -> Result<Json<device::SyncStatus>, podsync::Error> {
+    let username = split_format_json(&username_format)?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
+    let status = authed.update_sync(req).await?;
+    Ok(Json(status))
+}
+
 async fn get_subscriptions(
     State(state): State<AppState>,
     AxumPath((username, device_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
     headers: HeaderMap,
     Query(query): Query<QuerySince>,
 ) -> Result<Json<subscription::SubscriptionChangesToClient>, podsync::Error> {
     let device_id = split_format_json(&device_format)?;
-    let authed = authorize_request(&state.podsync, &username, &headers).await?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
     let result = authed.subscriptions(device_id, query.since).await?;
     Ok(Json(result))
 }
 
+async fn get_all_subscriptions(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    Query(query): Query<QueryJsonp>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Response, podsync::Error> {
+    let (username, format) = split_format(&username_format)?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
+    let urls = authed.current_subscriptions(None).await?;
+    url_list::render(format, &urls, query.jsonp.as_deref())
+}
+
+async fn get_device_subscriptions(
+    State(state): State<AppState>,
+    AxumPath((username, device_format)): AxumPath<(String, String)>,
+    Query(query): Query<QueryJsonp>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Response, podsync::Error> {
+    let (device_id, format) = split_format(&device_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let urls = authed.current_subscriptions(Some(device_id)).await?;
+    url_list::render(format, &urls, query.jsonp.as_deref())
+}
+
+async fn replace_subscriptions(
+    State(state): State<AppState>,
+    AxumPath((username, device_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    body: String,
+) -> Result<StatusCode, podsync::Error> {
+    let (device_id, format) = split_format(&device_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let urls = url_list::parse(format, &body)?;
+    authed.replace_subscriptions(device_id, urls).await?;
+    Ok(StatusCode::OK)
+}
+
 async fn update_subscriptions(
     State(state): State<AppState>,
     AxumPath((username, device_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
     headers: HeaderMap,
     Json(changes): Json<subscription::SubscriptionChangesFromClient>,
 ) -> Result<Json<podsync::UpdatedUrls>, podsync::Error> {
     let device_id = split_format_json(&device_format)?;
-    let authed = authorize_request(&state.podsync, &username, &headers).await?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
     let result = authed.update_subscriptions(device_id, changes).await?;
     Ok(Json(result))
 }
 
+async fn get_settings(
+    State(state): State<AppState>,
+    AxumPath((username, scope_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<settings::QuerySettings>,
+) -> Result<Json<settings::Settings>, podsync::Error> {
+    let scope = split_format_json(&scope_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let result = authed.settings(scope, query).await?;
+    Ok(Json(result))
+}
+
+async fn update_settings(
+    State(state): State<AppState>,
+    AxumPath((username, scope_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<settings::QuerySettings>,
+    Json(update): Json<settings::SettingsUpdate>,
+) -> Result<Json<settings::Settings>, podsync::Error> {
+    let scope = split_format_json(&scope_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let result = authed.update_settings(scope, query, update).await?;
+    Ok(Json(result))
+}
+
+/// Parse a result count from a path, e.g. "50.json"
+fn parse_count(count: &str) -> Result<usize, podsync::Error> {
+    count.parse().map_err(|e| {
+        error!("invalid count {count:?}: {e:?}");
+        podsync::Error::BadRequest
+    })
+}
+
+async fn get_toplist(
+    State(state): State<AppState>,
+    AxumPath(count_format): AxumPath<String>,
+    Query(query): Query<QueryJsonp>,
+) -> Result<Response, podsync::Error> {
+    let (count, format) = split_format(&count_format)?;
+    let podcasts = state.podsync.toplist(parse_count(count)?).await?;
+    url_list::render_podcasts(format, &podcasts, query.jsonp.as_deref())
+}
+
+async fn search(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(query): Query<directory::QuerySearch>,
+) -> Result<Response, podsync::Error> {
+    let (_, format) = split_format(uri.path())?;
+    let podcasts = state
+        .podsync
+        .search(query.q.as_deref().unwrap_or_default())
+        .await?;
+    url_list::render_podcasts(format, &podcasts, query.jsonp.as_deref())
+}
+
+async fn get_top_tags(
+    State(state): State<AppState>,
+    AxumPath(count_format): AxumPath<String>,
+) -> Result<Json<Vec<directory::Tag>>, podsync::Error> {
+    let count = split_format_json(&count_format)?;
+    let tags = state.podsync.top_tags(parse_count(count)?).await?;
+    Ok(Json(tags))
+}
+
+async fn get_tag_podcasts(
+    State(state): State<AppState>,
+    AxumPath((tag, count_format)): AxumPath<(String, String)>,
+) -> Result<Json<Vec<podcast::Podcast>>, podsync::Error> {
+    let count = split_format_json(&count_format)?;
+    let podcasts = state
+        .podsync
+        .tag_podcasts(&tag, parse_count(count)?)
+        .await?;
+    Ok(Json(podcasts))
+}
+
+/// Lists are public, but their owner also sees their private lists
+async fn get_podcast_lists(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<Vec<podcast_list::ListInfo>>, podsync::Error> {
+    let username = split_format_json(&username_format)?;
+
+    // authenticated as whoever the credentials name, who needn't be the lists' owner
+    let authed = match has_credentials(&state.podsync, &headers, &client) {
+        true => Some(authorize_own(&state.podsync, &headers, &client).await?),
+        false => None,
+    };
+    let lists = match authed {
+        Some(authed) if authed.username() == username => authed.podcast_lists().await?,
+        _ => state.podsync.public_podcast_lists(username).await?,
+    };
+    Ok(Json(lists))
+}
+
+async fn create_podcast_list(
+    State(state): State<AppState>,
+    AxumPath((username, create_format)): AxumPath<(String, String)>,
+    Query(query): Query<podcast_list::QueryList>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, podsync::Error> {
+    let (create, format) = split_format(&create_format)?;
+    if create != "create" {
+        return Err(podsync::Error::NotFound);
+    }
+    let title = query.title.ok_or_else(|| {
+        error!("podcast list created without a title");
+        podsync::Error::BadRequest
+    })?;
+
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let urls = url_list::parse(format, &body)?;
+    let (list, updated) = authed
+        .create_podcast_list(&title, query.public.unwrap_or(false), urls)
+        .await?;
+
+    let extension = &create_format[create.len()..];
+    let location = format!("/api/2/lists/{username}/list/{}{extension}", list.name);
+    Ok((
+        StatusCode::SEE_OTHER,
+        [(header::LOCATION, location)],
+        Json(updated),
+    )
+        .into_response())
+}
+
+async fn get_podcast_list(
+    State(state): State<AppState>,
+    AxumPath((username, name_format)): AxumPath<(String, String)>,
+    Query(query): Query<podcast_list::QueryList>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Response, podsync::Error> {
+    let (name, format) = split_format(&name_format)?;
+
+    let list = match state.podsync.public_podcast_list(&username, name).await? {
+        Some(list) => list,
+        None => {
+            let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+            authed.podcast_list(name).await?
+        }
+    };
+    url_list::render(format, &list.urls, query.jsonp.as_deref())
+}
+
+async fn update_podcast_list(
+    State(state): State<AppState>,
+    AxumPath((username, name_format)): AxumPath<(String, String)>,
+    Query(query): Query<podcast_list::QueryList>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<podcast_list::UpdatedList>, podsync::Error> {
+    let (name, format) = split_format(&name_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let urls = url_list::parse(format, &body)?;
+    let result = authed
+        .update_podcast_list(name, query.title, query.public, urls)
+        .await?;
+    Ok(Json(result))
+}
+
+async fn delete_podcast_list(
+    State(state): State<AppState>,
+    AxumPath((username, name_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let (name, _) = split_format(&name_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    authed.delete_podcast_list(name).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_favorites(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<Vec<podcast::PodcastEpisode>>, podsync::Error> {
+    let username = split_format_json(&username_format)?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
+    let favorites = authed.favorites().await?;
+    Ok(Json(favorites))
+}
+
+async fn get_device_updates(
+    State(state): State<AppState>,
+    AxumPath((username, device_format)): AxumPath<(String, String)>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<podsync::QueryUpdates>,
+) -> Result<Json<updates::DeviceUpdates>, podsync::Error> {
+    let device_id = split_format_json(&device_format)?;
+    let authed = authorize_request(&state.podsync, &username, &headers, &client).await?;
+    let result = authed.device_updates(device_id, query).await?;
+    Ok(Json(result))
+}
+
 async fn get_episodes(
     State(state): State<AppState>,
     AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
     headers: HeaderMap,
     Query(query): Query<podsync::QueryEpisodes>,
 ) -> Result<Json<episode::Episodes>, podsync::Error> {
     let username = split_format_json(&username_format)?;
-    let authed = authorize_request(&state.podsync, username, &headers).await?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
     let result = authed.episodes(query).await?;
     Ok(Json(result))
 }
@@ -225,11 +754,64 @@ async fn get_episodes(
 async fn update_episodes(
     State(state): State<AppState>,
     AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
     headers: HeaderMap,
     Json(body): Json<Vec<episode::Episode>>,
 ) -> Result<Json<podsync::UpdatedUrls>, podsync::Error> {
     let username = split_format_json(&username_format)?;
-    let authed = authorize_request(&state.podsync, username, &headers).await?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
+    let result = authed.update_episodes(body).await?;
+    Ok(Json(result))
+}
+
+async fn nextcloud_subscriptions(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<QueryOptionalSince>,
+) -> Result<Json<subscription::SubscriptionChangesToClient>, podsync::Error> {
+    let authed = authorize_own(&state.podsync, &headers, &client).await?;
+    let since = query.since.unwrap_or_else(Timestamp::zero);
+    let result = authed.subscriptions(NEXTCLOUD_DEVICE, since).await?;
+    Ok(Json(result))
+}
+
+async fn nextcloud_update_subscriptions(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(changes): Json<subscription::SubscriptionChangesFromClient>,
+) -> Result<Json<podsync::UpdatedUrls>, podsync::Error> {
+    let authed = authorize_own(&state.podsync, &headers, &client).await?;
+    let result = authed
+        .update_subscriptions(NEXTCLOUD_DEVICE, changes)
+        .await?;
+    Ok(Json(result))
+}
+
+async fn nextcloud_episodes(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(query): Query<QueryOptionalSince>,
+) -> Result<Json<episode::Episodes>, podsync::Error> {
+    let authed = authorize_own(&state.podsync, &headers, &client).await?;
+    let result = authed
+        .episodes(podsync::QueryEpisodes {
+            since: query.since,
+            ..Default::default()
+        })
+        .await?;
+    Ok(Json(result))
+}
+
+async fn nextcloud_update_episodes(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(body): Json<Vec<episode::Episode>>,
+) -> Result<Json<podsync::UpdatedUrls>, podsync::Error> {
+    let authed = authorize_own(&state.podsync, &headers, &client).await?;
     let result = authed.update_episodes(body).await?;
     Ok(Json(result))
 }
@@ -251,45 +833,236 @@ fn extract_session_id(headers: &HeaderMap) -> Option<SessionId> {
     None
 }
 
-async fn authorize_request(
-    podsync: &Arc<PodSync>,
-    username: &str,
-    headers: &HeaderMap,
-) -> podsync::Result<PodSyncAuthed<true>> {
-    if let Some(session_id) = extract_session_id(headers) {
-        // Cookie present: authenticate via session only, no fallback to basic auth
-        let authed = podsync.authenticate(session_id).await?;
-        return authed
-            .with_user(username)
-            .map(|a| {
-                debug!("authed (via cookie) user {}", a.username());
-                a
-            })
-            .map_err(|e| {
-                debug!("no auth via cookie");
-                e
-            });
-    }
-
-    // No cookie: require Authorization header
-    let auth_header = headers
-        .get(header::AUTHORIZATION)
-        .ok_or(podsync::Error::Unauthorized)?;
-    let auth_str = auth_header
-        .to_str()
-        .map_err(|_| podsync::Error::Unauthorized)?;
-
-    info!("login auth");
-    let auth: BasicAuth = auth_str.parse().map_err(|_| podsync::Error::Unauthorized)?;
-    let auth = auth.with_path_username(username).map_err(|e| {
-        error!("{e}");
-        podsync::Error::Unauthorized
-    })?;
-
-    podsync.login(auth, None).await
-}
-
-async fn log_middleware(mut req: Request, next: Next) -> Response {
+async fn get_audit(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    Query(query): Query<audit::QueryAudit>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<Vec<audit::AuditEvent>>, podsync::Error> {
+    let username = split_format_json(&username_format)?;
+    let authed = authorize_request(&state.podsync, username, &headers, &client).await?;
+    let events = authed.audit_events(query.since).await?;
+    Ok(Json(events))
+}
+
+async fn admin_audit(
+    State(state): State<AppState>,
+    Query(query): Query<audit::QueryAudit>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<Vec<audit::AuditEvent>>, podsync::Error> {
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    let events = admin
+        .audit_events(query.username.as_deref(), query.since)
+        .await?;
+    Ok(Json(events))
+}
+
+async fn admin_users(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<Vec<user::UserSummary>>, podsync::Error> {
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    let users = admin.users().await?;
+    Ok(Json(users))
+}
+
+async fn admin_stale_devices(
+    State(state): State<AppState>,
+    Query(query): Query<device::QueryStale>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<Json<Vec<device::StaleDevice>>, podsync::Error> {
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    let devices = admin.stale_devices(query.days()).await?;
+    Ok(Json(devices))
+}
+
+async fn admin_delete_user(
+    State(state): State<AppState>,
+    AxumPath(username_format): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let username = split_format_json(&username_format)?;
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    admin.delete_user(username).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn admin_reset_password(
+    State(state): State<AppState>,
+    AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Json(body): Json<user::NewPassword>,
+) -> Result<StatusCode, podsync::Error> {
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    admin
+        .reset_password(&username, &body.password, &client)
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn admin_disable_user(
+    State(state): State<AppState>,
+    AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    admin.set_disabled(&username, true).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn admin_enable_user(
+    State(state): State<AppState>,
+    AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    admin.set_disabled(&username, false).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn admin_revoke_sessions(
+    State(state): State<AppState>,
+    AxumPath(username): AxumPath<String>,
+    client: ClientInfo,
+    headers: HeaderMap,
+) -> Result<StatusCode, podsync::Error> {
+    let admin = authorize_admin(&state.podsync, &headers, &client).await?;
+    admin.revoke_sessions(&username).await?;
+    Ok(StatusCode::OK)
+}
+
+async fn authorize_request(
+    podsync: &Arc<PodSync>,
+    username: &str,
+    headers: &HeaderMap,
+    client: &ClientInfo,
+) -> podsync::Result<PodSyncAuthed<true>> {
+    authorize(podsync, Some(username), headers, client).await
+}
+
+/// As `authorize_request`, for paths without a username (e.g. Nextcloud's API)
+async fn authorize_own(
+    podsync: &Arc<PodSync>,
+    headers: &HeaderMap,
+    client: &ClientInfo,
+) -> podsync::Result<PodSyncAuthed<true>> {
+    authorize(podsync, None, headers, client).await
+}
+
+/// Authenticate as `path_username`, or if there's none, as whoever the credentials name
+async fn authorize(
+    podsync: &Arc<PodSync>,
+    path_username: Option<&str>,
+    headers: &HeaderMap,
+    client: &ClientInfo,
+) -> podsync::Result<PodSyncAuthed<true>> {
+    let authed = if let Some(proxy_user) = podsync.proxy_user(headers, client) {
+        // the proxy has authenticated the user, so it takes precedence
+        if let Some(username) = path_username {
+            check_proxy_user(proxy_user, username)?;
+        }
+        podsync.authenticate_proxy(proxy_user, client).await?
+    } else if let Some(session_id) = extract_session_id(headers) {
+        // Cookie present: authenticate via session only, no fallback to basic auth
+        podsync.authenticate(session_id, client).await?
+    } else {
+        // No cookie: require Authorization header, basic or bearer
+        let auth_header = headers
+            .get(header::AUTHORIZATION)
+            .ok_or(podsync::Error::Unauthorized)?;
+        let auth_str = auth_header
+            .to_str()
+            .map_err(|_| podsync::Error::Unauthorized)?;
+
+        let Some(token) = auth_str.strip_prefix("Bearer ") else {
+            info!("login auth");
+            let auth: BasicAuth = auth_str.parse().map_err(|_| podsync::Error::Unauthorized)?;
+            let username = path_username.map_or_else(|| auth.user().to_string(), str::to_string);
+            let auth = auth.with_path_username(&username).map_err(|e| {
+                error!("{e}");
+                podsync::Error::Unauthorized
+            })?;
+
+            return podsync.authenticate_basic(auth, client).await;
+        };
+        podsync.authenticate_token(token.trim(), client).await?
+    };
+
+    let authed = match path_username {
+        Some(username) => authed.with_user(username)?,
+        None => authed.own_user(),
+    };
+    debug!("authed user {}", authed.username());
+    Ok(authed)
+}
+
+/// Whether the request tries to authenticate, by any of the means `authorize_request` accepts
+fn has_credentials(podsync: &PodSync, headers: &HeaderMap, client: &ClientInfo) -> bool {
+    podsync.proxy_user(headers, client).is_some()
+        || extract_session_id(headers).is_some()
+        || headers.contains_key(header::AUTHORIZATION)
+}
+
+/// As `BasicAuth::with_path_username` does, only let the proxy's user access their own paths
+fn check_proxy_user(proxy_user: &str, path_username: &str) -> podsync::Result<()> {
+    if proxy_user == path_username {
+        return Ok(());
+    }
+
+    error!("path-username ({path_username:?}) <-> proxy-username ({proxy_user:?}) mismatch");
+    Err(podsync::Error::Unauthorized)
+}
+
+/// Like `authorize_request`, but for any admin user, rather than the user in the path
+async fn authorize_admin(
+    podsync: &Arc<PodSync>,
+    headers: &HeaderMap,
+    client: &ClientInfo,
+) -> podsync::Result<PodSyncAdmin> {
+    authorize_own(podsync, headers, client).await?.admin().await
+}
+
+/// Re-issue the session cookie for sessions that have been renewed
+async fn session_renewal_middleware(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let session_id = extract_session_id(req.headers());
+
+    let mut response = next.run(req).await;
+
+    // only renew on success - the handler will have rejected expired sessions
+    let Some(session_id) = session_id else {
+        return response;
+    };
+    if !response.status().is_success() {
+        return response;
+    }
+
+    match state.podsync.renew_session(&session_id).await {
+        Ok(true) => match session_cookie(&state, &session_id) {
+            Ok(cookie) => {
+                response.headers_mut().append(header::SET_COOKIE, cookie);
+            }
+            Err(e) => error!("couldn't create renewed cookie: {e:?}"),
+        },
+        Ok(false) => {}
+        Err(e) => error!("couldn't renew session: {e:?}"),
+    }
+
+    response
+}
+
+async fn log_middleware(mut req: Request, next: Next) -> Response {
     use std::fmt::{self, Display, Formatter};
 
     struct OptFmt<T>(Option<T>);
@@ -366,11 +1139,105 @@ mod test {
 
     use super::*;
     use base64_light::base64_encode as base64;
+    use serde_json::Value;
+
+    /// How a test request authenticates
+    enum Auth<'a> {
+        None,
+        /// Basic auth, with the password every test user gets
+        User(&'a str),
+        /// Basic auth, with the given password
+        Basic(&'a str, &'a str),
+        Bearer(&'a str),
+        Cookie(&'a Cookie<'a>),
+    }
+
+    fn app(backend: backend::Backend, config: podsync::Config) -> Router {
+        routes(Arc::new(PodSync::new(backend, config)), true)
+    }
+
+    /// A fresh backend with `users`, each with the password "hunter22"
+    async fn backend_with_users(users: &[&str]) -> backend::Backend {
+        let backend = backend::Backend(backend::test::create_db().await);
+        for user in users {
+            backend
+                .create_user(user, &auth::pwhash("hunter22"))
+                .await
+                .unwrap();
+        }
+        backend
+    }
+
+    async fn app_with_users(users: &[&str]) -> Router {
+        app(backend_with_users(users).await, Default::default())
+    }
+
+    fn request(method: &str, uri: &str, auth: Auth, body: &str) -> Request<Body> {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json");
+        let basic = |user: &str, pass: &str| format!("Basic {}", base64(&format!("{user}:{pass}")));
+        let req = match auth {
+            Auth::None => req,
+            Auth::User(user) => req.header("authorization", basic(user, "hunter22")),
+            Auth::Basic(user, pass) => req.header("authorization", basic(user, pass)),
+            Auth::Bearer(token) => req.header("authorization", format!("Bearer {token}")),
+            Auth::Cookie(cookie) => req.header("cookie", cookie.to_string()),
+        };
+        req.body(Body::from(body.to_string())).unwrap()
+    }
+
+    /// The response's status and body - parsed as JSON, or as a string if it isn't
+    async fn send_request(app: &Router, req: Request<Body>) -> (StatusCode, Option<Value>) {
+        let res = app.clone().oneshot(req).await.unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        if body.is_empty() {
+            return (status, None);
+        }
+        let body = serde_json::from_slice(&body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8(body.to_vec()).unwrap()));
+        (status, Some(body))
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        auth: Auth<'_>,
+        body: &str,
+    ) -> (StatusCode, Option<Value>) {
+        send_request(app, request(method, uri, auth, body)).await
+    }
+
+    /// The JSON body of a request that must succeed
+    async fn send_ok(app: &Router, method: &str, uri: &str, auth: Auth<'_>, body: &str) -> Value {
+        let (status, body) = send(app, method, uri, auth, body).await;
+        assert_eq!(status, StatusCode::OK, "{method} {uri}");
+        body.unwrap()
+    }
+
+    /// Log in, for a new session's cookie
+    async fn login(app: &Router, username: &str, auth: Auth<'_>) -> Cookie<'static> {
+        let uri = format!("/api/2/auth/{username}/login.json");
+        let res = app
+            .clone()
+            .oneshot(request("POST", &uri, auth, ""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let cookie = res.headers()["set-cookie"].to_str().unwrap();
+        Cookie::parse(cookie.to_string()).unwrap()
+    }
 
     #[tokio::test]
     async fn hello() {
         let db = backend::test::create_db().await;
-        let podsync = Arc::new(PodSync::new(backend::Backend(db)));
+        let podsync = Arc::new(PodSync::new(backend::Backend(db), Default::default()));
         let app = routes(podsync, true);
 
         let res = app
@@ -391,7 +1258,8 @@ mod test {
         query!(
             r#"
             INSERT INTO users
-            VALUES ("bob", ?, NULL);
+            (username, pwhash)
+            VALUES ("bob", ?);
             "#,
             pwhash,
         )
@@ -399,7 +1267,10 @@ mod test {
         .await
         .unwrap();
 
-        let app = routes(Arc::new(PodSync::new(backend::Backend(db))), true);
+        let app = routes(
+            Arc::new(PodSync::new(backend::Backend(db), Default::default())),
+            true,
+        );
         let bob_auth = format!("Basic {}", base64(&format!("{}:{}", "bob", pass)));
 
         // logging in succeeds
@@ -503,4 +1374,2268 @@ mod test {
             .unwrap();
         assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
     }
+
+    #[tokio::test]
+    async fn multiple_sessions() {
+        let app = app_with_users(&["bob"]).await;
+        let get = |uri: &'static str, cookie| send(&app, "GET", uri, Auth::Cookie(cookie), "");
+
+        // two devices log in, and get separate sessions
+        let phone = login(&app, "bob", Auth::User("bob")).await;
+        let tablet = login(&app, "bob", Auth::User("bob")).await;
+        assert_ne!(phone.value(), tablet.value());
+
+        for cookie in [&phone, &tablet] {
+            let (status, _) = get("/api/2/devices/bob.json", cookie).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        // logging out one leaves the other logged in
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/auth/bob/logout.json",
+            Auth::Cookie(&phone),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = get("/api/2/devices/bob.json", &phone).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = get("/api/2/devices/bob.json", &tablet).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // and logging out everywhere ends all sessions
+        let laptop = login(&app, "bob", Auth::User("bob")).await;
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/auth/bob/logout-all.json",
+            Auth::Cookie(&laptop),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        for cookie in [&tablet, &laptop] {
+            let (status, _) = get("/api/2/devices/bob.json", cookie).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn app_tokens() {
+        let app = app_with_users(&["bob"]).await;
+
+        let mint = |name: &'static str, body: &'static str| {
+            let app = &app;
+            async move {
+                let uri = format!("/api/2/tokens/bob/{name}.json");
+                let (status, json) = send(app, "POST", &uri, Auth::User("bob"), body).await;
+                assert_eq!(status, StatusCode::OK);
+                json.unwrap()["token"].as_str().unwrap().to_string()
+            }
+        };
+
+        // tokens must be scoped
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/tokens/bob/any.json",
+            Auth::User("bob"),
+            "{}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let phone = mint("phone", r#"{ "device": "phone" }"#).await;
+        let reader = mint("reader", r#"{ "read_only": true }"#).await;
+
+        // a device token works as a bearer token, for its own device only
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::Bearer(&phone),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/tablet.json?since=0",
+            Auth::Bearer(&phone),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // ... though it still reads the other devices' episode actions
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            r#"[
+                { "podcast": "https://a.example", "episode": "https://a.example/1", "action": "download", "device": "phone" },
+                { "podcast": "https://a.example", "episode": "https://a.example/2", "action": "download", "device": "tablet" }
+            ]"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, json) = send(
+            &app,
+            "GET",
+            "/api/2/episodes/bob.json?since=0",
+            Auth::Bearer(&phone),
+            "",
+        )
+        .await;
+        let actions = json.unwrap()["actions"].as_array().unwrap().clone();
+        assert_eq!(actions.len(), 2);
+        assert!(actions
+            .iter()
+            .any(|a| a["episode"] == "https://a.example/2" && a["device"] == "tablet"));
+        // ... unless it names another device
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/episodes/bob.json?since=0&device=tablet",
+            Auth::Bearer(&phone),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // a read-only token works as a basic auth password, but can't write
+        let reader_auth = || Auth::Basic("bob", &reader);
+        let (status, _) = send(&app, "GET", "/api/2/devices/bob.json", reader_auth(), "").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/phone.json",
+            reader_auth(),
+            r#"{ "add": ["http://example.com/feed"], "remove": [] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // tokens can't manage tokens
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/tokens/bob.json",
+            Auth::Bearer(&phone),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // sessions made with a token end when it's revoked
+        let cookie = login(&app, "bob", reader_auth()).await;
+
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            "/api/2/tokens/bob/reader.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(&app, "GET", "/api/2/devices/bob.json", reader_auth(), "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/devices/bob.json",
+            Auth::Cookie(&cookie),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // and the remaining token is still listed
+        let (_, json) = send(&app, "GET", "/api/2/tokens/bob.json", Auth::User("bob"), "").await;
+        let json = json.unwrap();
+        assert_eq!(json[0]["name"], "phone");
+        assert_eq!(json[0]["device"], "phone");
+        assert!(json[1].is_null());
+    }
+
+    #[tokio::test]
+    async fn login_lockout() {
+        let app = app_with_users(&["bob"]).await;
+
+        for _ in 0..5 {
+            let (status, _) = send(
+                &app,
+                "POST",
+                "/api/2/auth/bob/login.json",
+                Auth::Basic("bob", "wrong"),
+                "",
+            )
+            .await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        // locked out, even with the right password
+        let res = app
+            .oneshot(request(
+                "POST",
+                "/api/2/auth/bob/login.json",
+                Auth::User("bob"),
+                "",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "30");
+    }
+
+    #[tokio::test]
+    async fn register() {
+        let backend = backend_with_users(&[]).await;
+        backend
+            .create_invite(&invite::Invite {
+                code: "abcd".into(),
+                uses_left: 2,
+                created: Timestamp::now().unwrap(),
+            })
+            .await
+            .unwrap();
+
+        let config = podsync::Config {
+            registration: true,
+            ..Default::default()
+        };
+        let app = app(backend, config);
+
+        let register = |username: &str, invite: &str, password: &str| {
+            let uri = format!("/api/2/auth/{username}/register.json");
+            let body = serde_json::json!({ "invite": invite, "password": password }).to_string();
+            let app = &app;
+            async move { send(app, "POST", &uri, Auth::None, &body).await.0 }
+        };
+
+        assert_eq!(
+            register("bob", "dcba", "hunter22").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            register("bob", "", "hunter22").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            register("bob", "abcd", "short").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            register("bob!", "abcd", "hunter22").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(register("bob", "abcd", "hunter22").await, StatusCode::OK);
+
+        // bob can now log in
+        login(&app, "bob", Auth::User("bob")).await;
+
+        // a failed registration leaves the invite's uses alone
+        assert_eq!(
+            register("bob", "abcd", "hunter22").await,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(register("tim", "abcd", "hunter22").await, StatusCode::OK);
+
+        // and the invite is used up
+        assert_eq!(
+            register("amy", "abcd", "hunter22").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn register_disabled() {
+        let app = app_with_users(&[]).await;
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/auth/bob/register.json",
+            Auth::None,
+            r#"{"invite":"abcd","password":"hunter22"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn admin() {
+        let backend = backend_with_users(&["alice", "bob"]).await;
+        backend.set_user_admin("alice", true).await.unwrap();
+        let app = app(backend, Default::default());
+
+        // bob has a device, subscribed to two podcasts
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/devices/bob/phone.json",
+            Auth::User("bob"),
+            r#"{ "caption": "phone", "type": "mobile" }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/phone.json",
+            Auth::User("bob"),
+            r#"{ "add": ["https://a.example", "https://b.example"], "remove": [] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // but isn't an admin
+        let (status, _) = send(&app, "GET", "/api/admin/users.json", Auth::User("bob"), "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, json) = send(
+            &app,
+            "GET",
+            "/api/admin/users.json",
+            Auth::User("alice"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let json = json.unwrap();
+        assert_eq!(json[0]["username"], "alice");
+        assert_eq!(json[0]["is_admin"], true);
+        assert_eq!(json[1]["username"], "bob");
+        assert_eq!(json[1]["devices"], 1);
+        assert_eq!(json[1]["subscriptions"], 2);
+
+        // disabling bob locks him out, until he's enabled
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/admin/users/bob/disable.json",
+            Auth::User("alice"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/auth/bob/login.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/admin/users/bob/enable.json",
+            Auth::User("alice"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // password resets are checked against the password rules
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/admin/users/bob/password.json",
+            Auth::User("alice"),
+            r#"{ "password": "short" }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/admin/users/bob/password.json",
+            Auth::User("alice"),
+            r#"{ "password": "hunter22" }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // alice can't delete herself, but can delete bob
+        let delete = |uri: &'static str| send(&app, "DELETE", uri, Auth::User("alice"), "");
+        let (status, _) = delete("/api/admin/users/alice.json").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = delete("/api/admin/users/bob.json").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = delete("/api/admin/users/bob.json").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = delete("/api/admin/users/bob%2Falice.json").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn proxy_auth() {
+        let config = podsync::Config {
+            proxy: Some(proxy::Config {
+                header: header::HeaderName::from_static("remote-user"),
+                trusted: vec!["127.0.0.1".parse().unwrap()],
+                auto_provision: true,
+            }),
+            ..Default::default()
+        };
+        let app = app(backend_with_users(&[]).await, config);
+
+        let proxied = |method: &str, uri: &str, from: [u8; 4], auth: Auth| {
+            let mut req = request(method, uri, auth, "");
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((from, 1234))));
+            req.headers_mut()
+                .insert("remote-user", HeaderValue::from_static("bob"));
+            req
+        };
+        let proxy = [127, 0, 0, 1];
+
+        // bob is created on first sight
+        let req = proxied("GET", "/api/2/devices/bob.json", proxy, Auth::None);
+        let (status, _) = send_request(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // and can log in, getting a session
+        let req = proxied("POST", "/api/2/auth/bob/login.json", proxy, Auth::None);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().contains_key("set-cookie"));
+
+        // but only for his own paths
+        let req = proxied("GET", "/api/2/devices/tim.json", proxy, Auth::None);
+        let (status, _) = send_request(&app, req).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // the header is ignored from anyone but the proxy
+        let req = proxied("GET", "/api/2/devices/bob.json", [10, 0, 0, 1], Auth::None);
+        let (status, _) = send_request(&app, req).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // and provisioned users have no password to log in with
+        let req = request(
+            "GET",
+            "/api/2/devices/bob.json",
+            Auth::Basic("bob", "!"),
+            "",
+        );
+        let (status, _) = send_request(&app, req).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn change_password() {
+        let app = app_with_users(&["bob"]).await;
+
+        let phone = login(&app, "bob", Auth::User("bob")).await;
+        let laptop = login(&app, "bob", Auth::User("bob")).await;
+
+        let change = |current: &str, new: &str| {
+            let body =
+                serde_json::json!({ "current_password": current, "new_password": new }).to_string();
+            let (app, phone) = (&app, &phone);
+            async move {
+                let uri = "/api/2/auth/bob/password.json";
+                send(app, "POST", uri, Auth::Cookie(phone), &body).await.0
+            }
+        };
+        let devices = |cookie| {
+            let app = &app;
+            async move {
+                let uri = "/api/2/devices/bob.json";
+                send(app, "GET", uri, Auth::Cookie(cookie), "").await.0
+            }
+        };
+
+        assert_eq!(
+            change("wrong", "correct horse").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(change("hunter22", "short").await, StatusCode::BAD_REQUEST);
+        assert_eq!(change("hunter22", "correct horse").await, StatusCode::OK);
+
+        // the session used to change the password lives on, the others end
+        assert_eq!(devices(&phone).await, StatusCode::OK);
+        assert_eq!(devices(&laptop).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn audit_log() {
+        let backend = backend_with_users(&["alice", "bob"]).await;
+        backend.set_user_admin("alice", true).await.unwrap();
+        let app = app(backend, Default::default());
+
+        let from_client = |method: &str, uri: &str, auth: Auth| {
+            let mut req = request(method, uri, auth, "");
+            req.headers_mut()
+                .insert("user-agent", HeaderValue::from_static("AntennaPod/3.0"));
+            req.extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 1], 1234))));
+            send_request(&app, req)
+        };
+        let events = |uri: &'static str, user: &'static str| {
+            let res = from_client("GET", uri, Auth::User(user));
+            async move {
+                let (status, json) = res.await;
+                assert_eq!(status, StatusCode::OK);
+                json.unwrap()
+            }
+        };
+
+        let (status, _) = from_client(
+            "POST",
+            "/api/2/auth/bob/login.json",
+            Auth::Basic("bob", "wrong"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) =
+            from_client("POST", "/api/2/auth/bob/login.json", Auth::User("bob")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let json = events("/api/2/audit/bob.json", "bob").await;
+        let json = json.as_array().unwrap();
+        assert_eq!(json.len(), 2);
+        assert_eq!(json[0]["action"], "login");
+        assert_eq!(json[0]["success"], false);
+        assert_eq!(json[0]["addr"], "192.0.2.1");
+        assert_eq!(json[0]["user_agent"], "AntennaPod/3.0");
+        assert_eq!(json[1]["action"], "login");
+        assert_eq!(json[1]["success"], true);
+
+        // bob can only see his own history
+        let (status, _) = from_client("GET", "/api/2/audit/alice.json", Auth::User("bob")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = from_client("GET", "/api/admin/audit.json", Auth::User("bob")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // failures for unknown users aren't kept
+        let (status, _) = from_client(
+            "POST",
+            "/api/2/auth/nobody/login.json",
+            Auth::Basic("nobody", "wrong"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // but alice sees everyone's
+        let json = events("/api/admin/audit.json", "alice").await;
+        let users = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["username"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(users, ["bob", "bob"]);
+
+        let json = events("/api/admin/audit.json?username=alice", "alice").await;
+        assert_eq!(json.as_array().unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn simple_subscriptions() {
+        let app = app_with_users(&["bob"]).await;
+
+        for (device, body) in [
+            (
+                "phone",
+                r#"{ "add": ["https://a.example/?x=1&y=2", "https://b.example"], "remove": [] }"#,
+            ),
+            (
+                "laptop",
+                r#"{ "add": ["https://b.example", "https://c.example"], "remove": [] }"#,
+            ),
+            (
+                "laptop",
+                r#"{ "add": [], "remove": ["https://c.example"] }"#,
+            ),
+        ] {
+            let uri = format!("/api/2/subscriptions/bob/{device}.json");
+            let (status, _) = send(&app, "POST", &uri, Auth::User("bob"), body).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let get = |uri: &'static str| send(&app, "GET", uri, Auth::User("bob"), "");
+        let text = |body: Option<Value>| body.unwrap().as_str().unwrap().to_string();
+
+        assert_eq!(
+            get("/subscriptions/bob.json").await,
+            (
+                StatusCode::OK,
+                Some(serde_json::json!([
+                    "https://a.example/?x=1&y=2",
+                    "https://b.example"
+                ]))
+            )
+        );
+        assert_eq!(
+            get("/subscriptions/bob/laptop.txt").await,
+            (StatusCode::OK, Some("https://b.example\n".into()))
+        );
+        assert_eq!(
+            get("/subscriptions/bob/phone.jsonp?jsonp=cb").await,
+            (
+                StatusCode::OK,
+                Some(r#"cb(["https://a.example/?x=1&y=2","https://b.example"])"#.into())
+            )
+        );
+
+        let (status, opml) = get("/subscriptions/bob.opml").await;
+        assert_eq!(status, StatusCode::OK);
+        let opml = text(opml);
+        assert!(opml.contains(r#"xmlUrl="https://a.example/?x=1&amp;y=2""#));
+        assert!(opml.contains(r#"xmlUrl="https://b.example""#));
+
+        let (status, xml) = get("/subscriptions/bob.xml").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(text(xml).contains("<url>https://b.example</url>"));
+
+        assert_eq!(
+            get("/subscriptions/bob.jsonp").await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            get("/subscriptions/bob.jsonp?jsonp=alert(1)").await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            get("/subscriptions/bob.yaml").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn replace_subscriptions() {
+        let app = app_with_users(&["bob"]).await;
+
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/subscriptions/bob/phone.txt",
+            Auth::User("bob"),
+            "https://a.example\nhttps://b.example\n",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let opml = r#"<?xml version="1.0" encoding="utf-8"?>
+            <opml version="2.0">
+                <head><title>exported</title></head>
+                <body>
+                    <outline text="b" type="rss" xmlUrl="https://b.example"/>
+                    <outline text="news">
+                        <outline text="c" type="rss" xmlUrl="https://c.example/?a=1&amp;b=2"/>
+                    </outline>
+                </body>
+            </opml>"#;
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/subscriptions/bob/phone.opml",
+            Auth::User("bob"),
+            opml,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        assert_eq!(
+            send(
+                &app,
+                "GET",
+                "/subscriptions/bob/phone.txt",
+                Auth::User("bob"),
+                ""
+            )
+            .await,
+            (
+                StatusCode::OK,
+                Some("https://b.example\nhttps://c.example/?a=1&b=2\n".into())
+            )
+        );
+
+        // other devices see the difference
+        let (status, json) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let json = json.unwrap();
+        assert_eq!(json["remove"], serde_json::json!(["https://a.example"]));
+        let mut added = json["add"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|u| u.as_str().unwrap())
+            .collect::<Vec<_>>();
+        added.sort();
+        assert_eq!(added, ["https://b.example", "https://c.example/?a=1&b=2"]);
+
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/subscriptions/bob/phone.json",
+            Auth::User("bob"),
+            "[]",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            send(
+                &app,
+                "GET",
+                "/subscriptions/bob/phone.json",
+                Auth::User("bob"),
+                ""
+            )
+            .await,
+            (StatusCode::OK, Some(serde_json::json!([])))
+        );
+
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/subscriptions/bob/phone.xml",
+            Auth::User("bob"),
+            "<podcasts/>",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/subscriptions/bob/phone.opml",
+            Auth::User("bob"),
+            "<opml><body>",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn sync_devices() {
+        let app = app_with_users(&["bob"]).await;
+
+        for dev in ["phone", "laptop", "tablet"] {
+            let (status, _) = send(
+                &app,
+                "POST",
+                &format!("/api/2/devices/bob/{dev}.json"),
+                Auth::User("bob"),
+                "{}",
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/laptop.json",
+            Auth::User("bob"),
+            r#"{ "add": ["https://a.example"], "remove": [] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/api/2/sync-devices/bob.json",
+            Auth::User("bob"),
+            r#"{ "synchronize": [["phone", "laptop"]] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body.unwrap(),
+            serde_json::json!({
+                "synchronized": [["laptop", "phone"]],
+                "not-synchronized": ["tablet"],
+            })
+        );
+
+        // grouping shares existing subscriptions
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(
+            body.unwrap()["add"],
+            serde_json::json!(["https://a.example"])
+        );
+
+        // ... and later changes
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/phone.json",
+            Auth::User("bob"),
+            r#"{ "add": ["https://b.example"], "remove": ["https://a.example"] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/laptop.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        let body = body.unwrap();
+        assert_eq!(body["add"], serde_json::json!(["https://b.example"]));
+        assert_eq!(body["remove"], serde_json::json!(["https://a.example"]));
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/tablet.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body.unwrap()["add"], serde_json::json!([]));
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            "/api/2/sync-devices/bob.json",
+            Auth::User("bob"),
+            r#"{ "stop-synchronize": ["phone"] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body.unwrap(),
+            serde_json::json!({
+                "synchronized": [],
+                "not-synchronized": ["laptop", "phone", "tablet"],
+            })
+        );
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/sync-devices/bob.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body.unwrap()["synchronized"], serde_json::json!([]));
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/sync-devices/bob.json",
+            Auth::User("bob"),
+            r#"{ "synchronize": [["phone", "../x"]] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn retire_device() {
+        let app = app_with_users(&["bob"]).await;
+
+        for dev in ["phone", "tablet"] {
+            let (status, _) = send(
+                &app,
+                "POST",
+                &format!("/api/2/devices/bob/{dev}.json"),
+                Auth::User("bob"),
+                r#"{ "caption": "old", "type": "mobile" }"#,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/phone.json",
+            Auth::User("bob"),
+            r#"{ "add": ["https://a.example"], "remove": [] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/sync-devices/bob.json",
+            Auth::User("bob"),
+            r#"{ "synchronize": [["phone", "tablet"]] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/settings/bob/device.json?device=phone",
+            Auth::User("bob"),
+            r#"{ "set": { "volume": 11 } }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // a session for each device
+        let mut sessions = vec![];
+        for dev in ["phone", "tablet"] {
+            let cookie = login(&app, "bob", Auth::User("bob")).await;
+            let uri = format!("/api/2/subscriptions/bob/{dev}.json?since=0");
+            let (status, _) = send(&app, "GET", &uri, Auth::Cookie(&cookie), "").await;
+            assert_eq!(status, StatusCode::OK);
+            sessions.push(cookie);
+        }
+        let [phone, tablet] = &sessions[..] else {
+            unreachable!()
+        };
+        // and a token for each device
+        let mut tokens = vec![];
+        for dev in ["phone", "tablet"] {
+            let uri = format!("/api/2/tokens/bob/{dev}.json");
+            let body = format!(r#"{{ "device": "{dev}" }}"#);
+            let (status, json) = send(&app, "POST", &uri, Auth::User("bob"), &body).await;
+            assert_eq!(status, StatusCode::OK);
+            tokens.push(json.unwrap()["token"].as_str().unwrap().to_string());
+        }
+        let [phone_token, tablet_token] = &tokens[..] else {
+            unreachable!()
+        };
+
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            "/api/2/devices/bob/phone.json?remove_subscriptions=true",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        // hidden from the device list, out of its sync group, logged out, its token revoked
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/devices/bob.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        let body = body.unwrap();
+        let ids = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|dev| dev["id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["tablet"]);
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/sync-devices/bob.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(
+            body.unwrap(),
+            serde_json::json!({
+                "synchronized": [],
+                "not-synchronized": ["tablet"],
+            })
+        );
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/devices/bob.json",
+            Auth::Cookie(phone),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/devices/bob.json",
+            Auth::Cookie(tablet),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::Bearer(phone_token),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/tablet.json?since=0",
+            Auth::Bearer(tablet_token),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // subscriptions are removed, not forgotten
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        let body = body.unwrap();
+        assert_eq!(body["add"], serde_json::json!([]));
+        assert_eq!(body["remove"], serde_json::json!(["https://a.example"]));
+        // ... and the synced tablet keeps its own
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/tablet.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(
+            body.unwrap()["add"],
+            serde_json::json!(["https://a.example"])
+        );
+
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            "/api/2/devices/bob/phone.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // the ID can be reused, without the old device's settings (or, as they
+        // were removed, its subscriptions)
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/devices/bob/phone.json",
+            Auth::User("bob"),
+            r#"{ "caption": "new" }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/devices/bob.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        let body = body.unwrap();
+        assert_eq!(body[0]["id"], "phone");
+        assert_eq!(body[0]["caption"], "new");
+        assert_eq!(body[0]["type"], "other");
+        assert_eq!(body[0]["subscriptions"], 0);
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/settings/bob/device.json?device=phone",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body, Some(serde_json::json!({})));
+        // ... and the old token isn't accepted for it
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::Bearer(phone_token),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // subscriptions that aren't removed are inherited by the reused ID
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            "/api/2/devices/bob/tablet.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/devices/bob/tablet.json",
+            Auth::User("bob"),
+            "{}",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/subscriptions/bob/tablet.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body, Some(serde_json::json!(["https://a.example"])));
+    }
+
+    #[tokio::test]
+    async fn device_activity() {
+        let backend = backend_with_users(&["alice", "bob"]).await;
+        backend.set_user_admin("alice", true).await.unwrap();
+        // from before activity was recorded
+        backend
+            .update_device(
+                "bob",
+                "tablet",
+                device::DeviceUpdate {
+                    caption: None,
+                    r#type: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let db = backend.0.clone();
+        let app = app(backend, Default::default());
+
+        let from_app = |method: &str, uri: &str, user, body: &str| {
+            let mut req = request(method, uri, Auth::User(user), body);
+            req.headers_mut()
+                .insert("user-agent", HeaderValue::from_static("AntennaPod/3.0"));
+            send_request(&app, req)
+        };
+
+        for (method, uri, body) in [
+            ("POST", "/api/2/devices/bob/phone.json", "{}"),
+            (
+                "POST",
+                "/api/2/subscriptions/bob/phone.json",
+                r#"{ "add": ["https://a.example"], "remove": [] }"#,
+            ),
+            ("GET", "/api/2/updates/bob/phone.json?since=0", ""),
+            (
+                "POST",
+                "/api/2/episodes/bob.json",
+                r#"[{ "podcast": "https://a.example", "episode": "https://a.example/ep1",
+                      "action": "download", "device": "phone" }]"#,
+            ),
+            // never registered, so not recorded
+            (
+                "POST",
+                "/api/2/episodes/bob.json",
+                r#"[{ "podcast": "https://a.example", "episode": "https://a.example/ep1",
+                      "action": "download", "device": "ghost" }]"#,
+            ),
+        ] {
+            let (status, _) = from_app(method, uri, "bob", body).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let unregistered =
+            query!("SELECT COUNT(*) AS count FROM device_calls WHERE device = 'ghost'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(unregistered.count, 0);
+
+        let (_, body) = from_app("GET", "/api/2/devices/bob.json", "bob", "").await;
+        let body = body.unwrap();
+        assert_eq!(body[0]["id"], "phone");
+        assert_eq!(body[0]["last_seen"], 25);
+        assert_eq!(body[0]["user_agent"], "AntennaPod/3.0");
+        assert_eq!(
+            body[0]["api_calls"],
+            serde_json::json!({
+                "device": 25,
+                "episodes": 25,
+                "subscriptions": 25,
+                "updates": 25,
+            })
+        );
+        // absent, rather than null
+        assert_eq!(body[1]["id"], "tablet");
+        assert_eq!(
+            body[1].as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["caption", "id", "subscriptions", "type"]
+        );
+
+        let (status, _) = from_app("GET", "/api/admin/devices.json", "bob", "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = from_app("GET", "/api/admin/devices.json?days=1", "alice", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["username"], "bob");
+        assert_eq!(body[0]["id"], "tablet");
+
+        // a reused ID doesn't inherit the old device's activity
+        let (status, _) = from_app("DELETE", "/api/2/devices/bob/phone.json", "bob", "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = from_app("POST", "/api/2/devices/bob/phone.json", "bob", "{}").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = from_app("GET", "/api/2/devices/bob.json", "bob", "").await;
+        assert_eq!(
+            body.unwrap()[0]["api_calls"],
+            serde_json::json!({ "device": 25 })
+        );
+    }
+
+    #[tokio::test]
+    async fn device_updates() {
+        let app = app_with_users(&["bob"]).await;
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/phone.json",
+            Auth::User("bob"),
+            r#"{ "add": ["https://a.example"], "remove": [] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, "POST", "/api/2/episodes/bob.json", Auth::User("bob"), r#"[
+                { "podcast": "https://a.example", "episode": "https://a.example/1.mp3", "action": "download" },
+                { "podcast": "https://z.example", "episode": "https://z.example/1.mp3", "action": "download" }
+            ]"#)
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/api/2/updates/bob/phone.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body["add"][0]["url"], "https://a.example");
+        assert_eq!(body["rem"], serde_json::json!([]));
+        let updates = body["updates"].as_array().unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0]["url"], "https://a.example/1.mp3");
+        assert_eq!(updates[0]["podcast_url"], "https://a.example");
+        assert_eq!(updates[0]["status"], "download");
+        assert!(updates[0].get("action").is_none());
+        assert_eq!(body["timestamp"], 25);
+
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/updates/bob/phone.json?since=0&include_actions=true",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body.unwrap()["updates"][0]["action"]["action"], "download");
+
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/updates/bob/phone.json?since=25",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        let body = body.unwrap();
+        assert_eq!(body["add"], serde_json::json!([]));
+        assert_eq!(body["updates"], serde_json::json!([]));
+
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/updates/bob/phone.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn settings() {
+        let app = app_with_users(&["bob"]).await;
+
+        let podcast = "/api/2/settings/bob/podcast.json?podcast=https%3A%2F%2Fa.example";
+        let (status, body) = send(
+            &app,
+            "POST",
+            podcast,
+            Auth::User("bob"),
+            r#"{ "set": { "speed": 1.5, "skip_intro": 30, "tags": ["a"] } }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body.unwrap(),
+            serde_json::json!({ "speed": 1.5, "skip_intro": 30, "tags": ["a"] })
+        );
+
+        let (status, body) = send(
+            &app,
+            "POST",
+            podcast,
+            Auth::User("bob"),
+            r#"{ "set": { "speed": 2 }, "remove": ["tags", "missing"] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body.unwrap(),
+            serde_json::json!({ "speed": 2, "skip_intro": 30 })
+        );
+        assert_eq!(
+            send(&app, "GET", podcast, Auth::User("bob"), "")
+                .await
+                .1
+                .unwrap(),
+            serde_json::json!({ "speed": 2, "skip_intro": 30 })
+        );
+
+        // scopes are separate
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/settings/bob/podcast.json?podcast=https%3A%2F%2Fb.example",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body.unwrap(), serde_json::json!({}));
+        let (_, body) = send(
+            &app,
+            "POST",
+            "/api/2/settings/bob/account.json",
+            Auth::User("bob"),
+            r#"{ "set": { "theme": "dark" } }"#,
+        )
+        .await;
+        assert_eq!(body.unwrap(), serde_json::json!({ "theme": "dark" }));
+        let (status, body) = send(&app, "POST", "/api/2/settings/bob/episode.json?podcast=https%3A%2F%2Fa.example&episode=https%3A%2F%2Fa.example%2F1.mp3", Auth::User("bob"), r#"{ "set": { "position": 60 } }"#)
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.unwrap(), serde_json::json!({ "position": 60 }));
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/settings/bob/device.json?device=phone",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body.unwrap(), serde_json::json!({}));
+
+        for uri in [
+            "/api/2/settings/bob/device.json",
+            "/api/2/settings/bob/episode.json?podcast=https%3A%2F%2Fa.example",
+            "/api/2/settings/bob/other.json",
+        ] {
+            let (status, _) = send(&app, "GET", uri, Auth::User("bob"), "").await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+
+        // favourites are an episode setting
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/favorites/bob.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body.unwrap(), serde_json::json!([]));
+        let (status, _) = send(&app, "POST", "/api/2/settings/bob/episode.json?podcast=https%3A%2F%2Fa.example&episode=https%3A%2F%2Fa.example%2F1.mp3", Auth::User("bob"), r#"{ "set": { "is_favorite": true } }"#)
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/api/2/favorites/bob.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["url"], "https://a.example/1.mp3");
+        assert_eq!(body[0]["podcast_url"], "https://a.example");
+        assert!(body[0].get("status").is_none());
+    }
+
+    #[tokio::test]
+    async fn podcast_lists() {
+        let app = app_with_users(&["bob", "amy"]).await;
+
+        let res = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/api/2/lists/bob/create.txt?title=Onboarding%20Listening&public=true",
+                Auth::User("bob"),
+                "https://a.example\nhttps://b.example\n",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            res.headers()[header::LOCATION],
+            "/api/2/lists/bob/list/onboarding-listening.txt"
+        );
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/lists/bob/create.json?title=Private",
+            Auth::User("bob"),
+            r#"["https://c.example"]"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+
+        // names are unique
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/lists/bob/create.json?title=private",
+            Auth::User("bob"),
+            "[]",
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/lists/bob/create.json",
+            Auth::User("bob"),
+            "[]",
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/lists/bob/create.json?title=x",
+            Auth::None,
+            "[]",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // public lists need no authentication
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/api/2/lists/bob/list/onboarding-listening.txt",
+            Auth::None,
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, Some("https://a.example\nhttps://b.example\n".into()));
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/api/2/lists/bob/list/private.opml",
+            Auth::None,
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{body:?}");
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/api/2/lists/bob/list/private.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, Some(serde_json::json!(["https://c.example"])));
+
+        let (_, body) = send(&app, "GET", "/api/2/lists/bob.json", Auth::None, "").await;
+        assert_eq!(
+            body,
+            Some(serde_json::json!([
+                { "name": "onboarding-listening", "title": "Onboarding Listening", "public": true }
+            ]))
+        );
+        let (_, body) = send(&app, "GET", "/api/2/lists/bob.json", Auth::User("bob"), "").await;
+        assert_eq!(body.unwrap().as_array().unwrap().len(), 2);
+        // other users, logged in or not, see only the public ones
+        let cookie = login(&app, "amy", Auth::User("amy")).await;
+        for auth in [Auth::User("amy"), Auth::Cookie(&cookie)] {
+            let (status, body) = send(&app, "GET", "/api/2/lists/bob.json", auth, "").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                body,
+                Some(serde_json::json!([
+                    { "name": "onboarding-listening", "title": "Onboarding Listening", "public": true }
+                ]))
+            );
+        }
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/api/2/lists/bob/list/onboarding-listening.json",
+            Auth::User("amy"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            Some(serde_json::json!([
+                "https://a.example",
+                "https://b.example"
+            ]))
+        );
+        // but bad credentials are still refused
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/lists/bob.json",
+            Auth::Basic("amy", "wrong"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/api/2/lists/bob/list/private.txt?public=true",
+            Auth::User("bob"),
+            "https://d.example\n",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/api/2/lists/bob/list/private.txt",
+            Auth::None,
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, Some("https://d.example\n".into()));
+
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            "/api/2/lists/bob/list/private.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/api/2/lists/bob/list/private.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(
+            &app,
+            "DELETE",
+            "/api/2/lists/bob/list/private.json",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn directory() {
+        let db = backend_with_users(&["bob", "amy"]).await.0;
+        let app_with = |directory| {
+            let config = podsync::Config {
+                directory,
+                ..Default::default()
+            };
+            app(backend::Backend(db.clone()), config)
+        };
+        let app = app_with(Some(directory::Config { min_subscribers: 1 }));
+
+        for (user, subs) in [
+            (
+                "bob",
+                r#"["https://a.example/feed", "https://b.example/rss"]"#,
+            ),
+            ("amy", r#"["https://b.example/rss"]"#),
+        ] {
+            let (status, _) = send(
+                &app,
+                "PUT",
+                &format!("/subscriptions/{user}/phone.json"),
+                Auth::User(user),
+                subs,
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        // a second device doesn't count twice
+        let (status, _) = send(
+            &app,
+            "PUT",
+            "/subscriptions/bob/laptop.json",
+            Auth::User("bob"),
+            r#"["https://a.example/feed"]"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/settings/bob/podcast.json?podcast=https%3A%2F%2Fa.example%2Ffeed",
+            Auth::User("bob"),
+            r#"{ "set": { "tags": ["Tech", "news"] } }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&app, "GET", "/toplist/10.json", Auth::None, "").await;
+        assert_eq!(status, StatusCode::OK);
+        let top = body.unwrap();
+        assert_eq!(top[0]["url"], "https://b.example/rss");
+        assert_eq!(top[0]["subscribers"], 2);
+        assert_eq!(top[1]["url"], "https://a.example/feed");
+        assert_eq!(top[1]["subscribers"], 1);
+
+        assert_eq!(
+            send(&app, "GET", "/toplist/1.txt", Auth::None, "").await,
+            (StatusCode::OK, Some("https://b.example/rss\n".into()))
+        );
+        assert_eq!(
+            send(&app, "GET", "/search.txt?q=FEED", Auth::None, "").await,
+            (StatusCode::OK, Some("https://a.example/feed\n".into()))
+        );
+        let (status, body) = send(&app, "GET", "/search.json?q=example", Auth::None, "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.unwrap().as_array().unwrap().len(), 2);
+        let (status, _) = send(&app, "GET", "/search.json", Auth::None, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, "GET", "/other.json", Auth::None, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, "GET", "/favicon.ico", Auth::None, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, "GET", "/toplist/many.json", Auth::None, "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, body) = send(&app, "GET", "/api/2/tags/10.json", Auth::None, "").await;
+        assert_eq!(
+            body,
+            Some(serde_json::json!([
+                { "title": "news", "tag": "news", "usage": 1 },
+                { "title": "tech", "tag": "tech", "usage": 1 },
+            ]))
+        );
+        let (_, body) = send(&app, "GET", "/api/2/tag/tech/10.json", Auth::None, "").await;
+        let tagged = body.unwrap();
+        assert_eq!(tagged.as_array().unwrap().len(), 1);
+        assert_eq!(tagged[0]["url"], "https://a.example/feed");
+        let (_, body) = send(&app, "GET", "/api/2/tag/other/10.json", Auth::None, "").await;
+        assert_eq!(body, Some(serde_json::json!([])));
+
+        // podcasts with too few subscribers aren't listed, nor are their tags
+        let app = app_with(Some(directory::Config { min_subscribers: 2 }));
+        let get = |uri: &'static str| send(&app, "GET", uri, Auth::None, "");
+        assert_eq!(
+            get("/toplist/10.txt").await,
+            (StatusCode::OK, Some("https://b.example/rss\n".into()))
+        );
+        assert_eq!(get("/search.txt?q=feed").await, (StatusCode::OK, None));
+        assert_eq!(
+            get("/api/2/tags/10.json").await,
+            (StatusCode::OK, Some(serde_json::json!([])))
+        );
+
+        // off by default
+        let app = app_with(None);
+        for uri in [
+            "/toplist/10.json",
+            "/search.json?q=example",
+            "/api/2/tags/10.json",
+            "/api/2/tag/tech/10.json",
+        ] {
+            let (status, _) = send(&app, "GET", uri, Auth::None, "").await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn episode_history() {
+        let app = app_with_users(&["bob"]).await;
+
+        let positions = |body: serde_json::Value| {
+            body["actions"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|a| {
+                    (
+                        a["episode"]
+                            .as_str()
+                            .unwrap()
+                            .trim_start_matches("https://a.example/")
+                            .to_string(),
+                        a["position"].as_u64().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let play = |episode: &str, position: u32| {
+            format!(
+                r#"[{{ "podcast": "https://a.example", "episode": "https://a.example/{episode}", "action": "play",
+                    "timestamp": "2024-01-01T00:00:{position:02}",
+                    "started": 0, "position": {position}, "total": 100 }}]"#
+            )
+        };
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &play("ep1", 10),
+        )
+        .await;
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &play("ep1", 20),
+        )
+        .await;
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &play("ep2", 30),
+        )
+        .await;
+        // resent actions aren't recorded twice
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &play("ep1", 20),
+        )
+        .await;
+
+        let latest = vec![("ep1".to_string(), 20), ("ep2".to_string(), 30)];
+        let mut aggregated = positions(
+            send_ok(
+                &app,
+                "GET",
+                "/api/2/episodes/bob.json?since=0&aggregated=true",
+                Auth::User("bob"),
+                "",
+            )
+            .await,
+        );
+        aggregated.sort();
+        assert_eq!(aggregated, latest);
+        let mut default = positions(
+            send_ok(
+                &app,
+                "GET",
+                "/api/2/episodes/bob.json?since=0",
+                Auth::User("bob"),
+                "",
+            )
+            .await,
+        );
+        default.sort();
+        assert_eq!(default, latest);
+
+        assert_eq!(
+            positions(
+                send_ok(
+                    &app,
+                    "GET",
+                    "/api/2/episodes/bob.json?since=0&aggregated=false",
+                    Auth::User("bob"),
+                    ""
+                )
+                .await
+            ),
+            [
+                ("ep1".to_string(), 10),
+                ("ep1".to_string(), 20),
+                ("ep2".to_string(), 30),
+            ]
+        );
+        let body = send_ok(
+            &app,
+            "GET",
+            "/api/2/episodes/bob.json?since=0&aggregated=false&podcast=https%3A%2F%2Fb.example",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body["actions"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn stale_episode_actions() {
+        let app = app_with_users(&["bob"]).await;
+
+        let latest = || async {
+            let body = send_ok(
+                &app,
+                "GET",
+                "/api/2/episodes/bob.json?since=0",
+                Auth::User("bob"),
+                "",
+            )
+            .await;
+            let actions = body["actions"].as_array().unwrap().clone();
+            assert_eq!(actions.len(), 1);
+            (
+                actions[0]["action"].as_str().unwrap().to_string(),
+                actions[0]["position"].as_u64(),
+            )
+        };
+
+        let play = |time: &str, position: u32| {
+            format!(
+                r#"[{{ "podcast": "https://a.example", "episode": "https://a.example/ep1", "action": "play",
+                    "timestamp": "2024-01-01T{time}", "device": "phone",
+                    "started": 0, "position": {position}, "total": 100 }}]"#
+            )
+        };
+        let download = |time: &str| {
+            format!(
+                r#"[{{ "podcast": "https://a.example", "episode": "https://a.example/ep1", "action": "download",
+                    "timestamp": "2024-01-01T{time}", "device": "old-phone" }}]"#
+            )
+        };
+
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &play("12:00:00", 50),
+        )
+        .await;
+
+        // an old device catching up doesn't rewind progress
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &play("10:00:00", 10),
+        )
+        .await;
+        assert_eq!(latest().await, ("play".to_string(), Some(50)));
+
+        // nor does a stale download replace a later play
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &download("11:00:00"),
+        )
+        .await;
+        assert_eq!(latest().await, ("play".to_string(), Some(50)));
+
+        // newer actions still win, whatever they are
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &play("13:00:00", 70),
+        )
+        .await;
+        assert_eq!(latest().await, ("play".to_string(), Some(70)));
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &download("14:00:00"),
+        )
+        .await;
+        assert_eq!(latest().await, ("download".to_string(), None));
+
+        // within a single upload too
+        let batch = format!(
+            "[{}, {}]",
+            play("16:00:00", 90).trim_matches(['[', ']']),
+            play("15:00:00", 80).trim_matches(['[', ']']),
+        );
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            &batch,
+        )
+        .await;
+        assert_eq!(latest().await, ("play".to_string(), Some(90)));
+
+        // and an action without a timestamp can't be shown to be newer
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            r#"[{ "podcast": "https://a.example", "episode": "https://a.example/ep1", "action": "play",
+                  "device": "old-phone", "started": 0, "position": 5, "total": 100 }]"#,
+        )
+        .await;
+        assert_eq!(latest().await, ("play".to_string(), Some(90)));
+    }
+
+    #[tokio::test]
+    async fn nextcloud() {
+        let app = app_with_users(&["bob"]).await;
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/index.php/apps/gpoddersync/subscription_change/create",
+            Auth::User("bob"),
+            r#"{ "add": ["https://a.example"], "remove": [] }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/index.php/apps/gpoddersync/subscriptions?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body["add"], serde_json::json!(["https://a.example"]));
+        assert_eq!(body["timestamp"], 25);
+
+        // the same subscriptions as a gpodder device
+        let (_, body) = send(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/nextcloud.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(
+            body.unwrap()["add"],
+            serde_json::json!(["https://a.example"])
+        );
+
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/index.php/apps/gpoddersync/episode_action/create",
+            Auth::User("bob"),
+            r#"[{ "podcast": "https://a.example", "episode": "https://a.example/1.mp3",
+                "guid": "1", "action": "play", "timestamp": "2024-01-01T00:00:00",
+                "started": 0, "position": 10, "total": 100 }]"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(
+            &app,
+            "GET",
+            "/index.php/apps/gpoddersync/episode_action?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body["actions"][0]["guid"], "1");
+        assert_eq!(body["actions"][0]["position"], 10);
+
+        let (status, _) = send(
+            &app,
+            "GET",
+            "/index.php/apps/gpoddersync/subscriptions",
+            Auth::Basic("bob", "wrong"),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn url_sanitisation() {
+        let app = app_with_users(&["bob"]).await;
+
+        let body = send_ok(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/phone.json",
+            Auth::User("bob"),
+            r#"{
+                "add": [" http://Feed.example/x", "http://feed.example/x", "ftp://other.example/y"],
+                "remove": []
+            }"#,
+        )
+        .await;
+        assert_eq!(
+            body["update_urls"],
+            serde_json::json!([
+                [" http://Feed.example/x", "http://feed.example/x"],
+                ["ftp://other.example/y", ""],
+            ])
+        );
+
+        let body = send_ok(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body["add"], serde_json::json!(["http://feed.example/x"]));
+
+        let body = send_ok(&app, "POST", "/api/2/episodes/bob.json", Auth::User("bob"), r#"[
+                { "podcast": "http://FEED.example/x", "episode": "http://feed.example/1.mp3?utm_source=app", "action": "download" },
+                { "podcast": "http://feed.example/x", "episode": "not a url", "action": "download" }
+            ]"#)
+        .await;
+        assert_eq!(
+            body["update_urls"],
+            serde_json::json!([
+                ["http://FEED.example/x", "http://feed.example/x"],
+                [
+                    "http://feed.example/1.mp3?utm_source=app",
+                    "http://feed.example/1.mp3"
+                ],
+                ["not a url", ""],
+            ])
+        );
+
+        let body = send_ok(
+            &app,
+            "GET",
+            "/api/2/episodes/bob.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        let actions = body["actions"].as_array().unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["podcast"], "http://feed.example/x");
+        assert_eq!(actions[0]["episode"], "http://feed.example/1.mp3");
+
+        // list URLs too
+        let res = app
+            .clone()
+            .oneshot(request(
+                "POST",
+                "/api/2/lists/bob/create.txt?title=Shared&public=true",
+                Auth::User("bob"),
+                "http://Feed.example/x?utm_medium=list\njavascript:alert(1)\n",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::SEE_OTHER);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["update_urls"],
+            serde_json::json!([
+                [
+                    "http://Feed.example/x?utm_medium=list",
+                    "http://feed.example/x"
+                ],
+                ["javascript:alert(1)", ""],
+            ])
+        );
+        let body = send_ok(
+            &app,
+            "PUT",
+            "/api/2/lists/bob/list/shared.json",
+            Auth::User("bob"),
+            r#"["http://feed.example/x", " http://FEED.example/x"]"#,
+        )
+        .await;
+        assert_eq!(
+            body["update_urls"],
+            serde_json::json!([[" http://FEED.example/x", "http://feed.example/x"]])
+        );
+        let body = send_ok(
+            &app,
+            "GET",
+            "/api/2/lists/bob/list/shared.json",
+            Auth::None,
+            "",
+        )
+        .await;
+        assert_eq!(body, serde_json::json!(["http://feed.example/x"]));
+
+        // and the podcast & episode settings are stored under
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/settings/bob/podcast.json?podcast=http%3A%2F%2FFeed.example%2Fx%3Futm_source%3Dapp",
+            Auth::User("bob"),
+            r#"{ "set": { "tags": ["news"] } }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let body = send_ok(
+            &app,
+            "GET",
+            "/api/2/settings/bob/podcast.json?podcast=http%3A%2F%2Ffeed.example%2Fx",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body, serde_json::json!({ "tags": ["news"] }));
+        let (status, _) = send(
+            &app,
+            "POST",
+            "/api/2/settings/bob/episode.json?podcast=http%3A%2F%2Ffeed.example%2Fx&episode=javascript%3Aalert(1)",
+            Auth::User("bob"),
+            r#"{ "set": { "is_favorite": true } }"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn sanitizes_stored_urls() {
+        let backend = backend_with_users(&["bob"]).await;
+
+        // stored before URLs were sanitised
+        let now = Timestamp::now().unwrap();
+        let changes = subscription::SubscriptionChangesFromClient {
+            add: vec!["https://Feed.example/x".into()],
+            remove: vec![],
+        };
+        backend
+            .update_subscriptions("bob", "phone", &changes, now)
+            .await
+            .unwrap();
+        let actions = serde_json::from_str(
+            r#"[{ "podcast": "https://Feed.example/x", "episode": "https://Feed.example/x/1.mp3",
+                  "action": "play", "timestamp": "2024-01-01T12:00:00",
+                  "started": 0, "position": 50, "total": 100 }]"#,
+        )
+        .unwrap();
+        backend.update_episodes("bob", now, actions).await.unwrap();
+
+        backend.sanitize_stored_urls().await.unwrap();
+
+        // which only happens once
+        let changes = subscription::SubscriptionChangesFromClient {
+            add: vec!["https://Feed.example/y".into()],
+            remove: vec![],
+        };
+        backend
+            .update_subscriptions("bob", "laptop", &changes, now)
+            .await
+            .unwrap();
+        backend.sanitize_stored_urls().await.unwrap();
+        assert_eq!(
+            backend
+                .current_subscriptions("bob", Some("laptop"))
+                .await
+                .unwrap(),
+            ["https://Feed.example/y"]
+        );
+
+        let app = app(backend, Default::default());
+
+        // removing the old spelling removes the stored subscription
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/subscriptions/bob/phone.json",
+            Auth::User("bob"),
+            r#"{ "add": [], "remove": ["https://Feed.example/x"] }"#,
+        )
+        .await;
+        let body = send_ok(
+            &app,
+            "GET",
+            "/api/2/subscriptions/bob/phone.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        assert_eq!(body["add"], serde_json::json!([]));
+        assert_eq!(
+            body["remove"],
+            serde_json::json!(["https://feed.example/x"])
+        );
+
+        // and the stored episode is the same episode as its sanitised spelling
+        send_ok(
+            &app,
+            "POST",
+            "/api/2/episodes/bob.json",
+            Auth::User("bob"),
+            r#"[{ "podcast": "https://feed.example/x", "episode": "https://feed.example/x/1.mp3",
+                  "action": "play", "timestamp": "2024-01-01T10:00:00",
+                  "started": 0, "position": 10, "total": 100 }]"#,
+        )
+        .await;
+        let body = send_ok(
+            &app,
+            "GET",
+            "/api/2/episodes/bob.json?since=0",
+            Auth::User("bob"),
+            "",
+        )
+        .await;
+        let actions = body["actions"].as_array().unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["episode"], "https://feed.example/x/1.mp3");
+        assert_eq!(actions[0]["position"], 50);
+    }
 }
@@ -1,20 +1,68 @@
+use std::result;
+
 use crate::podsync::{Error, Result};
 use log::error;
 
-pub fn split_format_json(s: &str) -> Result<&str> {
-    let (a, b) = s.split_once('.').ok_or(Error::BadRequest).map_err(|e| {
-        error!("couldn't split json {s:?} on '.'");
-        e
+/// The formats of the gpodder Simple API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Jsonp,
+    Opml,
+    Txt,
+    Xml,
+}
+
+impl std::str::FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "jsonp" => Ok(Self::Jsonp),
+            "opml" => Ok(Self::Opml),
+            "txt" => Ok(Self::Txt),
+            "xml" => Ok(Self::Xml),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn split_format(s: &str) -> Result<(&str, Format)> {
+    let (a, b) = s.split_once('.').ok_or_else(|| {
+        error!("couldn't split format {s:?} on '.'");
+        Error::BadRequest
     })?;
 
-    err_unless_json(b).map_err(|e| {
-        error!("\"json\" not found in {b:?}");
-        e
+    let format = b.parse().map_err(|()| {
+        error!("unknown format {b:?}");
+        Error::BadRequest
+    })?;
+
+    Ok((a, format))
+}
+
+pub fn split_format_json(s: &str) -> Result<&str> {
+    let (a, format) = split_format(s)?;
+
+    err_unless_json(format).inspect_err(|_| {
+        error!("\"json\" not found in {s:?}");
     })?;
 
     Ok(a)
 }
 
-fn err_unless_json(s: &str) -> Result<()> {
-    (s == "json").then_some(()).ok_or(Error::BadRequest)
+fn err_unless_json(format: Format) -> Result<()> {
+    (format == Format::Json)
+        .then_some(())
+        .ok_or(Error::BadRequest)
+}
+
+/// Names which appear in URL paths (before a ".json" suffix) and, for the
+/// file backend, in file paths - so we keep them to a conservative set of characters
+pub fn valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '@'))
 }
@@ -0,0 +1,59 @@
+use serde::Serialize;
+
+/// A podcast, as described to clients. We don't fetch feeds, so only the
+/// URL is known - the remaining fields are present for clients which
+/// require them.
+#[derive(Debug, Serialize)]
+pub struct Podcast {
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    pub website: Option<String>,
+    pub subscribers: u32,
+    pub subscribers_last_week: u32,
+    pub mygpo_link: Option<String>,
+    pub logo_url: Option<String>,
+}
+
+impl Podcast {
+    pub fn from_url(url: String) -> Self {
+        Self {
+            url,
+            title: String::new(),
+            description: String::new(),
+            website: None,
+            subscribers: 0,
+            subscribers_last_week: 0,
+            mygpo_link: None,
+            logo_url: None,
+        }
+    }
+}
+
+/// An episode, as described to clients. As with [`Podcast`], only the URLs are known.
+#[derive(Debug, Serialize)]
+pub struct PodcastEpisode {
+    pub title: String,
+    pub url: String,
+    pub podcast_title: String,
+    pub podcast_url: String,
+    pub description: String,
+    pub website: Option<String>,
+    pub mygpo_link: Option<String>,
+    pub released: Option<String>,
+}
+
+impl PodcastEpisode {
+    pub fn from_urls(podcast_url: String, url: String) -> Self {
+        Self {
+            title: String::new(),
+            url,
+            podcast_title: String::new(),
+            podcast_url,
+            description: String::new(),
+            website: None,
+            mygpo_link: None,
+            released: None,
+        }
+    }
+}
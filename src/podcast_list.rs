@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListInfo {
+    pub name: String,
+    pub title: String,
+    /// Readable without authentication
+    pub public: bool,
+}
+
+/// A named, user-owned list of podcast URLs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PodcastList {
+    #[serde(flatten)]
+    pub info: ListInfo,
+    pub urls: Vec<String>,
+}
+
+/// The URLs rewritten when storing a list, as `(from, to)`
+#[derive(Debug, Serialize)]
+pub struct UpdatedList {
+    pub update_urls: Vec<(String, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryList {
+    /// Required when creating a list
+    pub title: Option<String>,
+    pub public: Option<bool>,
+    pub jsonp: Option<String>,
+}
+
+/// A list's name, as used in its path, from its title: "Onboarding Listening" -> "onboarding-listening"
+pub fn name_from_title(title: &str) -> Option<String> {
+    let name = title
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let name = name[..name.len().min(64)].trim_end_matches('-');
+
+    crate::path_format::valid_name(name).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            name_from_title("Onboarding Listening").as_deref(),
+            Some("onboarding-listening")
+        );
+        assert_eq!(
+            name_from_title("  Rust & Go, 2024!").as_deref(),
+            Some("rust-go-2024")
+        );
+        assert_eq!(name_from_title(&"long ".repeat(20)).unwrap().len(), 64);
+        assert_eq!(name_from_title("!!!"), None);
+        assert_eq!(name_from_title(""), None);
+    }
+}
@@ -1,27 +1,80 @@
-use std::{result, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    result,
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::auth::{AuthAttempt, SessionId};
+use crate::audit::{AuditAction, AuditEvent};
+use crate::auth::{
+    check_new_password, pwhash, verify_pwhash, AuthAttempt, PasswordCheck, SessionId, NO_PASSWORD,
+};
 use crate::backend::Backend;
-use crate::device::{DeviceAndSub, DeviceUpdate};
+use crate::client::ClientInfo;
+use crate::device::{
+    ApiCall, DeviceAndSub, DeviceUpdate, QueryRetire, StaleDevice, SyncRequest, SyncStatus,
+};
+use crate::directory::{self, Tag};
 use crate::episode::{Episode, Episodes, Time};
+use crate::invite::Registration;
+use crate::lockout::{self, Key, Lockout};
+use crate::podcast::{Podcast, PodcastEpisode};
+use crate::podcast_list::{self, ListInfo, PodcastList, UpdatedList};
+use crate::proxy;
+use crate::sanitize::{sanitize, sanitize_all, sanitize_recorded};
+use crate::session::Session;
+use crate::settings::{QuerySettings, Settings, SettingsTarget, SettingsUpdate};
 use crate::subscription::{SubscriptionChangesFromClient, SubscriptionChangesToClient};
 use crate::time::Timestamp;
+use crate::token::{self, AppToken, NewToken, TokenRequest, TokenScope};
+use crate::updates::{DeviceUpdates, EpisodeUpdate};
+use crate::user::{valid_username, PasswordChange, User, UserSummary};
+
+pub struct PodSync {
+    backend: Backend,
+    config: Config,
+    lockout: Lockout,
+}
 
-pub struct PodSync(Backend);
+pub struct Config {
+    /// How long a session lasts before it must be renewed
+    pub session_lifetime: Duration,
+    /// How long a session may go unused before it's expired
+    pub session_idle_timeout: Duration,
+    pub lockout: lockout::Config,
+    /// Whether accounts may be created over HTTP, with an invite code
+    pub registration: bool,
+    pub proxy: Option<proxy::Config>,
+    /// Serve the podcast directory (toplist, search & tags), if set
+    pub directory: Option<directory::Config>,
+}
 
 pub struct PodSyncAuthed<const USER_MATCH: bool = false> {
     sync: Arc<PodSync>,
-    session_id: SessionId,
+    session: Option<AuthedSession>, // None if authed via basic auth
+    scope: Option<TokenScope>,      // None if authed with the account's password
+    username: String,
+    client: ClientInfo,
+}
+
+pub struct PodSyncAdmin {
+    sync: Arc<PodSync>,
     username: String,
 }
 
+struct AuthedSession {
+    id: SessionId,
+    device: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct UpdatedUrls {
     // important: this timestamp is used by future client synchronisations
@@ -33,17 +86,34 @@ pub struct UpdatedUrls {
 #[derive(Debug, Deserialize, Default)]
 pub struct QueryEpisodes {
     pub since: Option<Timestamp>,
-    #[allow(dead_code)]
+    /// Only the latest action per episode (the default), rather than every action received
     pub aggregated: Option<bool>,
     pub podcast: Option<String>,
     pub device: Option<String>,
 }
 
+impl QueryEpisodes {
+    pub fn is_aggregated(&self) -> bool {
+        self.aggregated.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryUpdates {
+    pub since: Timestamp,
+    #[serde(default)]
+    pub include_actions: bool,
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Error {
     Internal,
     Unauthorized,
+    Forbidden,
     BadRequest,
+    NotFound,
+    Conflict,
+    TooManyRequests { retry_after: Duration },
 }
 
 pub type Result<T> = result::Result<T, Error>;
@@ -53,25 +123,60 @@ impl IntoResponse for Error {
         let status = match self {
             Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
             Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::TooManyRequests { retry_after } => {
+                // round up, so clients don't retry a moment too soon
+                let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, secs.to_string())],
+                )
+                    .into_response();
+            }
         };
         status.into_response()
     }
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        const DAY: u64 = 24 * 60 * 60;
+
+        Self {
+            session_lifetime: Duration::from_secs(14 * DAY),
+            session_idle_timeout: Duration::from_secs(7 * DAY),
+            lockout: Default::default(),
+            registration: false,
+            proxy: None,
+            directory: None,
+        }
+    }
+}
+
 impl PodSync {
-    pub fn new(backend: Backend) -> Self {
-        Self(backend)
+    pub fn new(backend: Backend, mut config: Config) -> Self {
+        let lockout = Lockout::new(std::mem::take(&mut config.lockout));
+
+        Self {
+            backend,
+            config,
+            lockout,
+        }
     }
 
-    pub async fn login(
-        self: &Arc<Self>,
-        auth_attempt: AuthAttempt,
-        client_session_id: Option<SessionId>,
-    ) -> Result<PodSyncAuthed<true>> {
-        let username = auth_attempt.user();
+    pub fn session_lifetime(&self) -> Duration {
+        self.config.session_lifetime
+    }
+
+    fn session_expired(&self, session: &Session, now: Timestamp) -> bool {
+        session.expires <= now || session.last_used + self.config.session_idle_timeout <= now
+    }
 
-        let user = self.0.find_user(username).await.map_err(|e| {
+    async fn find_enabled_user(&self, username: &str) -> Result<User> {
+        let user = self.backend.find_user(username).await.map_err(|e| {
             if matches!(e, crate::backend::FindError::NotFound) {
                 error!("rejecting non-existant user {}", username);
                 Error::Unauthorized
@@ -81,161 +186,1292 @@ impl PodSync {
             }
         })?;
 
-        if auth_attempt.calc_pwhash() != user.pwhash {
-            error!("wrong password for user {}", username);
+        if user.disabled {
+            error!("rejecting disabled user {}", username);
             return Err(Error::Unauthorized);
         }
 
-        let ok = |session_id| {
-            Ok(PodSyncAuthed {
-                sync: Arc::clone(self),
-                session_id,
-                username: auth_attempt.user().to_string(),
-            })
-        };
+        Ok(user)
+    }
+
+    /// Check a user's password or app token, returning the token if one was used
+    async fn check_credentials(&self, auth_attempt: &AuthAttempt) -> Result<Option<AppToken>> {
+        let username = auth_attempt.user();
+
+        if token::is_token(auth_attempt.pass()) {
+            let token = self.check_token(auth_attempt.pass()).await?;
+            if token.username != username {
+                error!("token for {} used by {username}", token.username);
+                return Err(Error::Unauthorized);
+            }
+            return Ok(Some(token));
+        }
+
+        let user = self.find_enabled_user(username).await?;
 
-        let db_session_id = match user.session_id {
-            Some(ref id) => {
-                let session_id = SessionId::from_str(&id).map_err(|()| {
-                    error!("invalid stored session_id: {:?}", user.session_id);
-                    Error::Internal
-                })?;
-                Some(session_id)
+        match auth_attempt.verify(&user.pwhash) {
+            PasswordCheck::Match => {}
+            PasswordCheck::MatchNeedsRehash => {
+                let pwhash = auth_attempt.calc_pwhash();
+
+                if self.backend.update_pwhash(username, &pwhash).await {
+                    info!("{username} login: upgraded legacy password hash");
+                } else {
+                    // not fatal, we'll try again next login
+                    error!("{username} login: couldn't upgrade legacy password hash");
+                }
+            }
+            PasswordCheck::Mismatch => {
+                error!("wrong password for user {}", username);
+                return Err(Error::Unauthorized);
             }
-            None => None,
+        }
+
+        Ok(None)
+    }
+
+    async fn check_token(&self, token: &str) -> Result<AppToken> {
+        let token = self
+            .backend
+            .find_token(&token::hash(token))
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => {
+                    error!("rejecting unknown app token");
+                    Error::Unauthorized
+                }
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+
+        self.find_enabled_user(&token.username).await?;
+
+        self.backend
+            .touch_token(&token.token_hash, now()?)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        debug!("{} authed via app token {:?}", token.username, token.name);
+        Ok(token)
+    }
+
+    /// Reject attempts from locked out users/addresses, otherwise run
+    /// `check`, tracking whether it fails
+    async fn limit_attempts<T>(
+        &self,
+        keys: &[Key],
+        check: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        if let Err(retry_after) = self.lockout.check(keys) {
+            let keys = keys.iter().map(Key::to_string).collect::<Vec<_>>();
+            warn!(
+                "rejecting locked out login ({}), retry in {}s",
+                keys.join(", "),
+                retry_after.as_secs()
+            );
+            return Err(Error::TooManyRequests { retry_after });
+        }
+
+        let result = check.await;
+        match result {
+            Ok(_) => self.lockout.record_success(keys),
+            Err(Error::Unauthorized) => self.lockout.record_failure(keys),
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Record an event in the user's audit log. Failing to record it
+    /// doesn't fail the request.
+    async fn audit(&self, username: &str, action: AuditAction, success: bool, client: &ClientInfo) {
+        let Ok(time) = now() else {
+            return;
         };
 
-        match (client_session_id, db_session_id) {
-            (None, None) => {
-                // initial login
-                let session_id = SessionId::new();
-                let session_id_str = session_id.to_string();
+        // anyone can try to log in as anyone, so only keep events for real users
+        if let Err(crate::backend::FindError::NotFound) = self.backend.find_user(username).await {
+            info!("not auditing {action:?} for unknown user {username:?} (success={success})");
+            return;
+        }
 
-                if !self.0.update_user(username, Some(&session_id_str)).await {
-                    error!("couldn't login user {}", username);
-                    return Err(Error::Internal);
+        let event = AuditEvent::new(username, time, action, success, client);
+        // errors are logged by the backend
+        let _ = self.backend.add_audit_event(&event).await;
+    }
+
+    /// Run `f`, recording its outcome in the user's audit log
+    async fn audited<T>(
+        &self,
+        username: &str,
+        action: AuditAction,
+        client: &ClientInfo,
+        f: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let result = f.await;
+        match result {
+            Ok(_) => self.audit(username, action, true, client).await,
+            // not the user's doing
+            Err(Error::Internal) => {}
+            Err(_) => self.audit(username, action, false, client).await,
+        }
+        result
+    }
+
+    /// Check a user's credentials, without creating a session
+    pub async fn authenticate_basic(
+        self: &Arc<Self>,
+        auth_attempt: AuthAttempt,
+        client: &ClientInfo,
+    ) -> Result<PodSyncAuthed<true>> {
+        let keys = Key::for_attempt(Some(auth_attempt.user()), client.addr);
+        let result = self
+            .limit_attempts(&keys, self.check_credentials(&auth_attempt))
+            .await;
+
+        // this happens on every request, so only failures are worth recording
+        if matches!(result, Err(ref e) if !matches!(e, Error::Internal)) {
+            self.audit(auth_attempt.user(), AuditAction::Login, false, client)
+                .await;
+        }
+        let token = result?;
+
+        Ok(PodSyncAuthed {
+            sync: Arc::clone(self),
+            session: None,
+            scope: token.as_ref().map(AppToken::scope),
+            username: auth_attempt.user().to_string(),
+            client: client.clone(),
+        })
+    }
+
+    /// Authenticate with an app token, e.g. from `Authorization: Bearer ...`
+    pub async fn authenticate_token(
+        self: &Arc<Self>,
+        token: &str,
+        client: &ClientInfo,
+    ) -> Result<PodSyncAuthed> {
+        let keys = Key::for_attempt(None, client.addr);
+        let token = self.limit_attempts(&keys, self.check_token(token)).await?;
+
+        Ok(PodSyncAuthed {
+            sync: Arc::clone(self),
+            session: None,
+            scope: Some(token.scope()),
+            username: token.username,
+            client: client.clone(),
+        })
+    }
+
+    /// The username asserted by a trusted reverse proxy, if any
+    pub fn proxy_user<'h>(&self, headers: &'h HeaderMap, client: &ClientInfo) -> Option<&'h str> {
+        self.config.proxy.as_ref()?.user(headers, client)
+    }
+
+    /// Authenticate a user named by a trusted reverse proxy, creating them if configured to
+    pub async fn authenticate_proxy(
+        self: &Arc<Self>,
+        username: &str,
+        client: &ClientInfo,
+    ) -> Result<PodSyncAuthed> {
+        let auto_provision = self
+            .config
+            .proxy
+            .as_ref()
+            .is_some_and(|proxy| proxy.auto_provision);
+
+        match self.backend.find_user(username).await {
+            Err(crate::backend::FindError::NotFound) if auto_provision => {
+                if !valid_username(username) {
+                    error!("can't provision invalid username {username:?}");
+                    return Err(Error::Unauthorized);
                 }
 
-                info!("{username} login: new session created");
-                ok(session_id)
+                match self.backend.create_user(username, NO_PASSWORD).await {
+                    // raced with another request
+                    Ok(()) | Err(crate::backend::CreateError::AlreadyExists) => {}
+                    Err(crate::backend::CreateError::Internal) => return Err(Error::Internal),
+                }
+                info!("{username} provisioned via proxy");
             }
-            (Some(client), Some(db_id)) => {
-                if client == db_id {
+            _ => {}
+        }
+
+        self.find_enabled_user(username).await?;
+        debug!("{username} authed via proxy");
+
+        Ok(PodSyncAuthed {
+            sync: Arc::clone(self),
+            session: None,
+            scope: None,
+            username: username.to_string(),
+            client: client.clone(),
+        })
+    }
+
+    pub async fn login(
+        self: &Arc<Self>,
+        auth_attempt: AuthAttempt,
+        client_session_id: Option<SessionId>,
+        client: &ClientInfo,
+    ) -> Result<PodSyncAuthed<true>> {
+        let keys = Key::for_attempt(Some(auth_attempt.user()), client.addr);
+        let token = self
+            .audited(
+                auth_attempt.user(),
+                AuditAction::Login,
+                client,
+                self.limit_attempts(&keys, self.check_credentials(&auth_attempt)),
+            )
+            .await?;
+
+        self.start_session(auth_attempt.user(), token, client_session_id, client)
+            .await
+    }
+
+    /// Log in a user named by a trusted reverse proxy
+    pub async fn login_proxy(
+        self: &Arc<Self>,
+        username: &str,
+        client_session_id: Option<SessionId>,
+        client: &ClientInfo,
+    ) -> Result<PodSyncAuthed<true>> {
+        self.audited(
+            username,
+            AuditAction::Login,
+            client,
+            self.authenticate_proxy(username, client),
+        )
+        .await?;
+
+        self.start_session(username, None, client_session_id, client)
+            .await
+    }
+
+    /// Reuse the client's session if it's still valid, otherwise create a new one
+    async fn start_session(
+        self: &Arc<Self>,
+        username: &str,
+        token: Option<AppToken>,
+        client_session_id: Option<SessionId>,
+        client: &ClientInfo,
+    ) -> Result<PodSyncAuthed<true>> {
+        let now = now()?;
+
+        let ok = |session_id, device, scope| {
+            Ok(PodSyncAuthed {
+                sync: Arc::clone(self),
+                session: Some(AuthedSession {
+                    id: session_id,
+                    device,
+                }),
+                scope,
+                username: username.to_string(),
+                client: client.clone(),
+            })
+        };
+
+        if let Some(client_session_id) = client_session_id {
+            match self
+                .backend
+                .find_session(&client_session_id.to_string())
+                .await
+            {
+                Ok(session) if self.session_expired(&session, now) => {
+                    info!("{username} login: session expired");
+                    self.backend
+                        .delete_session(&session.id)
+                        .await
+                        .map_err(|()| Error::Internal)?;
+                }
+                Ok(session) if session.username == username => {
+                    let scope = self.session_scope(&session).await?;
+
+                    self.backend
+                        .touch_session(&session.id, now, None)
+                        .await
+                        .map_err(|()| Error::Internal)?;
+
                     info!("{username} login: session check passed");
-                    ok(client)
-                } else {
-                    info!("{username} login: session check failed");
-                    Err(Error::Internal)
+                    return ok(client_session_id, session.device, scope);
+                }
+                Ok(_) => {
+                    info!("{username} login: session belongs to another user");
+                }
+                Err(crate::backend::FindError::NotFound) => {
+                    // logged out / expired, but kept their cookie
+                    info!("{username} login: session not found");
+                }
+                Err(e) => {
+                    error!("{username} login: couldn't look up session: {e:?}");
+                    return Err(Error::Internal);
                 }
             }
-            (Some(_), None) => {
-                // logged out but somehow kept their token?
-                info!("{username} login: no session in db");
+        }
+
+        let session_id = SessionId::new();
+        let session = Session {
+            id: session_id.to_string(),
+            username: username.to_string(),
+            device: None,
+            user_agent: client.user_agent.clone(),
+            created: now,
+            last_used: now,
+            expires: now + self.config.session_lifetime,
+            app_token: token.as_ref().map(|t| t.token_hash.clone()),
+        };
+
+        self.backend.create_session(&session).await.map_err(|()| {
+            error!("couldn't login user {}", username);
+            Error::Internal
+        })?;
+
+        info!("{username} login: new session created");
+        ok(session_id, None, token.as_ref().map(AppToken::scope))
+    }
+
+    /// Sessions created with an app token carry its scope, and end with it
+    async fn session_scope(&self, session: &Session) -> Result<Option<TokenScope>> {
+        let Some(ref token_hash) = session.app_token else {
+            return Ok(None);
+        };
+
+        match self.backend.find_token(token_hash).await {
+            Ok(token) => Ok(Some(token.scope())),
+            Err(crate::backend::FindError::NotFound) => {
+                error!("{} session's app token revoked", session.username);
                 Err(Error::Unauthorized)
             }
-            (None, Some(db_id)) => {
-                // logging in again, client's forgot their token
-                info!("{username} login: fresh login");
-                ok(db_id)
-            }
+            Err(crate::backend::FindError::Internal) => Err(Error::Internal),
+        }
+    }
+
+    pub async fn authenticate(
+        self: &Arc<Self>,
+        session_id: SessionId,
+        client: &ClientInfo,
+    ) -> Result<PodSyncAuthed> {
+        let session = self
+            .backend
+            .find_session(&session_id.to_string())
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => {
+                    error!("no user found for session {session_id}");
+                    Error::Unauthorized
+                }
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+
+        let now = now()?;
+        if self.session_expired(&session, now) {
+            error!("{} session {session_id} expired", session.username);
+            self.audit(&session.username, AuditAction::Session, false, client)
+                .await;
+            self.backend
+                .delete_session(&session.id)
+                .await
+                .map_err(|()| Error::Internal)?;
+            return Err(Error::Unauthorized);
         }
+
+        let scope = self.session_scope(&session).await?;
+
+        self.backend
+            .touch_session(&session.id, now, None)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        debug!("found user by session");
+        Ok(PodSyncAuthed {
+            sync: Arc::clone(self),
+            session: Some(AuthedSession {
+                id: session_id,
+                device: session.device,
+            }),
+            scope,
+            username: session.username,
+            client: client.clone(),
+        })
     }
+}
+
+impl PodSync {
+    /// Extend a session if it's past the halfway point of its lifetime.
+    /// Returns whether the session was renewed (and so needs a fresh cookie).
+    pub async fn renew_session(&self, session_id: &SessionId) -> Result<bool> {
+        let session = match self.backend.find_session(&session_id.to_string()).await {
+            Ok(s) => s,
+            Err(crate::backend::FindError::NotFound) => return Ok(false),
+            Err(crate::backend::FindError::Internal) => return Err(Error::Internal),
+        };
 
-    pub async fn authenticate(self: &Arc<Self>, session_id: SessionId) -> Result<PodSyncAuthed> {
-        let session_str = session_id.to_string();
+        let now = now()?;
+        let lifetime = self.config.session_lifetime;
+        if self.session_expired(&session, now) || session.expires > now + lifetime / 2 {
+            return Ok(false);
+        }
 
-        let users = self
-            .0
-            .users_with_session(&session_str)
+        let expires = now + lifetime;
+        self.backend
+            .update_session_expiry(&session.id, expires)
             .await
             .map_err(|()| Error::Internal)?;
 
-        match &users[..] {
-            [] => {
-                error!("no user found for session {session_id}");
-                Err(Error::Unauthorized)
+        info!("{} session renewed until {expires}", session.username);
+        Ok(true)
+    }
+}
+
+impl PodSync {
+    /// Create an account, using up one of the invite's uses
+    pub async fn register(
+        &self,
+        username: &str,
+        registration: Registration,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        if !self.config.registration {
+            return Err(Error::NotFound);
+        }
+
+        if !valid_username(username) {
+            error!("can't register invalid username {username:?}");
+            return Err(Error::BadRequest);
+        }
+        check_new_password(username, &registration.password).map_err(|e| {
+            error!("can't register {username:?}: {e}");
+            Error::BadRequest
+        })?;
+
+        // invite codes are guessable only by brute force, so count failures against the address
+        let keys = Key::for_attempt(None, client.addr);
+        self.limit_attempts(&keys, self.check_invite(&registration.invite))
+            .await?;
+
+        match self.backend.find_user(username).await {
+            Ok(_) => {
+                error!("can't register {username:?}: already exists");
+                return Err(Error::Conflict);
             }
-            [user] => {
-                assert_eq!(user.session_id, Some(session_str));
-
-                debug!("found user by session");
-                Ok(PodSyncAuthed {
-                    sync: Arc::clone(self),
-                    session_id,
-                    username: user.username.clone(),
-                })
+            Err(crate::backend::FindError::NotFound) => {}
+            Err(crate::backend::FindError::Internal) => return Err(Error::Internal),
+        }
+
+        self.backend
+            .create_user(username, &pwhash(&registration.password))
+            .await
+            .map_err(|e| match e {
+                crate::backend::CreateError::AlreadyExists => Error::Conflict,
+                crate::backend::CreateError::Internal => Error::Internal,
+            })?;
+
+        // only now use up the invite, so a failed creation leaves it intact,
+        // and if it's been used up meanwhile, undo the creation
+        if let Err(e) = self.backend.use_invite(&registration.invite).await {
+            error!("couldn't use invite for {username:?}, removing: {e:?}");
+            if let Err(e) = self.backend.delete_user(username).await {
+                error!("couldn't remove {username:?} after failed registration: {e:?}");
             }
-            _ => {
-                error!("multiple users found for session {session_id}");
-                Err(Error::Internal)
+            return Err(match e {
+                crate::backend::FindError::NotFound => Error::Unauthorized,
+                crate::backend::FindError::Internal => Error::Internal,
+            });
+        }
+
+        info!("{username} registered with an invite");
+        self.audit(username, AuditAction::Register, true, client)
+            .await;
+        Ok(())
+    }
+
+    async fn check_invite(&self, code: &str) -> Result<()> {
+        match self.backend.find_invite(code).await {
+            Ok(invite) if invite.uses_left > 0 => Ok(()),
+            Ok(_) | Err(crate::backend::FindError::NotFound) => {
+                error!("rejecting unknown invite");
+                Err(Error::Unauthorized)
             }
+            Err(crate::backend::FindError::Internal) => Err(Error::Internal),
+        }
+    }
+}
+
+impl PodSync {
+    /// `username`'s public podcast lists, for anyone to read
+    pub async fn public_podcast_lists(&self, username: &str) -> Result<Vec<ListInfo>> {
+        let lists = self
+            .backend
+            .podcast_lists(username)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        Ok(lists.into_iter().filter(|list| list.public).collect())
+    }
+
+    /// The named list, if it exists and is public
+    pub async fn public_podcast_list(
+        &self,
+        username: &str,
+        name: &str,
+    ) -> Result<Option<PodcastList>> {
+        match self.backend.podcast_list(username, name).await {
+            Ok(list) if list.info.public => Ok(Some(list)),
+            Ok(_) | Err(crate::backend::FindError::NotFound) => Ok(None),
+            Err(crate::backend::FindError::Internal) => Err(Error::Internal),
+        }
+    }
+}
+
+/// A directory of podcasts, built from every user's subscriptions
+impl PodSync {
+    /// The listed podcasts, those with enough subscribers
+    async fn catalog(&self) -> Result<Vec<Podcast>> {
+        let Some(ref config) = self.config.directory else {
+            debug!("directory disabled");
+            return Err(Error::NotFound);
+        };
+
+        let subscribers = self
+            .backend
+            .podcast_subscribers()
+            .await
+            .map_err(|()| Error::Internal)?
+            .into_iter()
+            .filter(|(_, count)| *count >= config.min_subscribers)
+            .collect();
+
+        Ok(directory::ranked(subscribers))
+    }
+
+    /// The listed podcasts' tags
+    async fn podcast_tags(&self) -> Result<HashMap<String, HashSet<String>>> {
+        let listed = self
+            .catalog()
+            .await?
+            .into_iter()
+            .map(|p| p.url)
+            .collect::<HashSet<_>>();
+
+        let values = self
+            .backend
+            .podcast_setting_values(directory::TAGS)
+            .await
+            .map_err(|()| Error::Internal)?
+            .into_iter()
+            .filter(|(_, podcast, _)| listed.contains(podcast))
+            .collect();
+
+        Ok(directory::podcast_tags(values))
+    }
+
+    pub async fn toplist(&self, count: usize) -> Result<Vec<Podcast>> {
+        let mut podcasts = self.catalog().await?;
+        podcasts.truncate(count.min(directory::MAX_RESULTS));
+        Ok(podcasts)
+    }
+
+    pub async fn search(&self, query: &str) -> Result<Vec<Podcast>> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            error!("empty podcast search");
+            return Err(Error::BadRequest);
         }
+
+        let podcasts = self
+            .catalog()
+            .await?
+            .into_iter()
+            .filter(|p| {
+                p.url.to_lowercase().contains(&query) || p.title.to_lowercase().contains(&query)
+            })
+            .take(directory::MAX_RESULTS)
+            .collect::<Vec<_>>();
+
+        info!("search for {query:?}, {} results", podcasts.len());
+        Ok(podcasts)
+    }
+
+    pub async fn top_tags(&self, count: usize) -> Result<Vec<Tag>> {
+        let tags = self.podcast_tags().await?;
+        Ok(directory::top_tags(
+            &tags,
+            count.min(directory::MAX_RESULTS),
+        ))
+    }
+
+    /// The most subscribed podcasts carrying `tag`
+    pub async fn tag_podcasts(&self, tag: &str, count: usize) -> Result<Vec<Podcast>> {
+        let tag = tag.to_lowercase();
+        let tags = self.podcast_tags().await?;
+
+        Ok(self
+            .catalog()
+            .await?
+            .into_iter()
+            .filter(|p| tags.get(&p.url).is_some_and(|tags| tags.contains(&tag)))
+            .take(count.min(directory::MAX_RESULTS))
+            .collect())
     }
 }
 
 impl PodSyncAuthed {
+    /// For APIs without a username in their paths, act as whoever authenticated
+    pub fn own_user(self) -> PodSyncAuthed<true> {
+        PodSyncAuthed {
+            sync: self.sync,
+            session: self.session,
+            scope: self.scope,
+            username: self.username,
+            client: self.client,
+        }
+    }
+
     pub fn with_user(self, username: &str) -> Result<PodSyncAuthed<true>> {
         if username == self.username {
             Ok(PodSyncAuthed {
                 sync: self.sync,
-                session_id: self.session_id,
+                session: self.session,
+                scope: self.scope,
                 username: self.username,
+                client: self.client,
             })
         } else {
             error!(
                 "mismatching session & username: session={{ username: {}, session_id: {} }}, username={username}",
-                self.username, self.session_id,
+                self.username,
+                match self.session {
+                    Some(ref s) => s.id.to_string(),
+                    None => "<none>".into(),
+                },
             );
             Err(Error::Unauthorized)
         }
     }
-}
+}
+
+impl PodSyncAuthed<true> {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Account-level operations need the account's password, not an app token
+    fn check_full_access(&self) -> Result<()> {
+        match self.scope {
+            None => Ok(()),
+            Some(_) => {
+                error!("{} app token used for account operation", self.username);
+                Err(Error::Forbidden)
+            }
+        }
+    }
+
+    fn check_write(&self) -> Result<()> {
+        match self.scope {
+            Some(TokenScope {
+                read_only: true, ..
+            }) => {
+                error!("{} read-only app token used for write", self.username);
+                Err(Error::Forbidden)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_device(&self, device_id: &str) -> Result<()> {
+        match self.scope {
+            Some(TokenScope {
+                device: Some(ref allowed),
+                ..
+            }) if allowed != device_id => {
+                error!(
+                    "{} app token for device {allowed} used for device {device_id}",
+                    self.username
+                );
+                Err(Error::Forbidden)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn logout(&self, client: &ClientInfo) -> Result<()> {
+        let username = &self.username;
+
+        let Some(ref session) = self.session else {
+            info!("{username} logout: no session");
+            return Ok(());
+        };
+        info!("{username} logout");
+
+        self.sync
+            .backend
+            .delete_session(&session.id.to_string())
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        self.sync
+            .audit(username, AuditAction::Logout, true, client)
+            .await;
+        Ok(())
+    }
+
+    pub async fn logout_all(&self, client: &ClientInfo) -> Result<()> {
+        self.check_full_access()?;
+
+        let username = &self.username;
+        info!("{username} logout: all sessions");
+
+        self.sync
+            .backend
+            .delete_sessions_for_user(username, None)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        self.sync
+            .audit(username, AuditAction::LogoutAll, true, client)
+            .await;
+        Ok(())
+    }
+
+    /// Change our password, ending every other session
+    pub async fn change_password(&self, change: PasswordChange, client: &ClientInfo) -> Result<()> {
+        self.sync
+            .audited(
+                &self.username,
+                AuditAction::PasswordChange,
+                client,
+                self.set_password(change, client),
+            )
+            .await
+    }
+
+    async fn set_password(&self, change: PasswordChange, client: &ClientInfo) -> Result<()> {
+        self.check_full_access()?;
+
+        let username = &self.username;
+        let keys = Key::for_attempt(Some(username), client.addr);
+        self.sync
+            .limit_attempts(&keys, async {
+                let user = self.sync.find_enabled_user(username).await?;
+
+                match verify_pwhash(&change.current_password, &user.pwhash) {
+                    PasswordCheck::Match | PasswordCheck::MatchNeedsRehash => Ok(()),
+                    PasswordCheck::Mismatch => {
+                        error!("{username} password change: wrong current password");
+                        Err(Error::Unauthorized)
+                    }
+                }
+            })
+            .await?;
+
+        check_new_password(username, &change.new_password).map_err(|e| {
+            error!("{username} password change: {e}");
+            Error::BadRequest
+        })?;
+
+        if !self
+            .sync
+            .backend
+            .update_pwhash(username, &pwhash(&change.new_password))
+            .await
+        {
+            return Err(Error::Internal);
+        }
+
+        let keep = self.session.as_ref().map(|s| s.id.to_string());
+        self.sync
+            .backend
+            .delete_sessions_for_user(username, keep.as_deref())
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        info!("{username} changed their password, other sessions ended");
+        Ok(())
+    }
+
+    pub async fn audit_events(&self, since: Option<Timestamp>) -> Result<Vec<AuditEvent>> {
+        self.check_full_access()?;
+
+        self.sync
+            .backend
+            .audit_events(Some(&self.username), since.unwrap_or_else(Timestamp::zero))
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    pub fn session_id(&self) -> Option<&SessionId> {
+        self.session.as_ref().map(|s| &s.id)
+    }
+
+    /// Record the device's activity, and associate our session (if any) with it
+    async fn note_device(&self, device_id: &str, call: ApiCall) -> Result<()> {
+        self.seen_device(device_id, call).await?;
+
+        let Some(ref session) = self.session else {
+            return Ok(());
+        };
+        if session.device.as_deref() == Some(device_id) {
+            return Ok(());
+        }
+
+        self.sync
+            .backend
+            .touch_session(&session.id.to_string(), now()?, Some(device_id))
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    async fn seen_device(&self, device_id: &str, call: ApiCall) -> Result<()> {
+        let addr = self.client.addr.map(|a| a.to_string());
+
+        self.sync
+            .backend
+            .touch_device(
+                &self.username,
+                device_id,
+                call,
+                now()?,
+                self.client.user_agent.as_deref(),
+                addr.as_deref(),
+            )
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    pub async fn tokens(&self) -> Result<Vec<AppToken>> {
+        self.check_full_access()?;
+
+        self.sync
+            .backend
+            .tokens_for_user(&self.username)
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    pub async fn create_token(&self, name: &str, request: TokenRequest) -> Result<NewToken> {
+        self.check_full_access()?;
+
+        let username = &self.username;
+        if !crate::path_format::valid_name(name) {
+            error!("{username} invalid app token name {name:?}");
+            return Err(Error::BadRequest);
+        }
+        if request.device.is_none() && !request.read_only {
+            error!("{username} app token {name:?} must be scoped to a device or read-only");
+            return Err(Error::BadRequest);
+        }
+
+        let secret = token::generate();
+        let app_token = AppToken {
+            username: username.clone(),
+            name: name.into(),
+            token_hash: token::hash(&secret),
+            device: request.device,
+            read_only: request.read_only,
+            created: now()?,
+            last_used: None,
+        };
+
+        self.sync
+            .backend
+            .create_token(&app_token)
+            .await
+            .map_err(|e| match e {
+                crate::backend::CreateError::AlreadyExists => {
+                    error!("{username} app token {name:?} already exists");
+                    Error::Conflict
+                }
+                crate::backend::CreateError::Internal => Error::Internal,
+            })?;
+
+        info!(
+            "{username} created app token {name:?}, device={}, read_only={}",
+            app_token.device.as_deref().unwrap_or("<any>"),
+            app_token.read_only,
+        );
+
+        Ok(NewToken {
+            name: app_token.name,
+            token: secret,
+        })
+    }
+
+    pub async fn revoke_token(&self, name: &str) -> Result<()> {
+        self.check_full_access()?;
+
+        let username = &self.username;
+        self.sync
+            .backend
+            .delete_token(username, name)
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+
+        info!("{username} revoked app token {name:?}");
+        Ok(())
+    }
+
+    pub async fn devices(&self) -> Result<Vec<DeviceAndSub>> {
+        let username = &self.username;
+        trace!("{username} getting devices");
+
+        self.sync
+            .backend
+            .devices_for_user(username)
+            .await
+            .inspect(|devs| info!("{username}, {} devices", devs.len()))
+            .map_err(|()| Error::Internal)
+    }
+
+    pub async fn update_device(&self, device_id: &str, update: DeviceUpdate) -> Result<()> {
+        let username = &self.username;
+        info!("{username} updating device {device_id}: {update:?}");
+        self.check_write()?;
+        self.check_device(device_id)?;
+
+        self.sync
+            .backend
+            .update_device(username, device_id, update)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        // once registered, so a new device's first call is recorded
+        self.note_device(device_id, ApiCall::Device).await
+    }
+
+    /// Retire a device, freeing its ID for reuse. Subscriptions are kept (and inherited by
+    /// a device reusing the ID) unless `query` asks for them to be removed
+    pub async fn retire_device(&self, device_id: &str, query: QueryRetire) -> Result<()> {
+        let username = &self.username;
+        self.check_write()?;
+        self.check_device(device_id)?;
+
+        self.sync
+            .backend
+            .retire_device(username, device_id, now()?, query.remove_subscriptions)
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+
+        info!(
+            "{username} retired device {device_id}, remove_subscriptions={}",
+            query.remove_subscriptions
+        );
+        Ok(())
+    }
+
+    pub async fn sync_status(&self) -> Result<SyncStatus> {
+        let username = &self.username;
+        trace!("{username} getting sync status");
+
+        let synchronized = self
+            .sync
+            .backend
+            .sync_groups(username)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        let not_synchronized = self
+            .devices()
+            .await?
+            .into_iter()
+            .map(|dev| dev.id)
+            .filter(|id| !synchronized.iter().flatten().any(|dev| dev == id))
+            .collect();
+
+        Ok(SyncStatus {
+            synchronized,
+            not_synchronized,
+        })
+    }
+
+    /// Group & ungroup devices. Newly grouped devices are each given the
+    /// union of their group's subscriptions.
+    pub async fn update_sync(&self, req: SyncRequest) -> Result<SyncStatus> {
+        let username = &self.username;
+        info!("{username} updating sync groups: {req:?}");
+        self.check_full_access()?;
+
+        let names = req.synchronize.iter().flatten();
+        if let Some(bad) = names
+            .chain(&req.stop_synchronize)
+            .find(|dev| !crate::path_format::valid_name(dev))
+        {
+            warn!("{username} tried to sync invalid device {bad:?}");
+            return Err(Error::BadRequest);
+        }
+
+        let groups = self
+            .sync
+            .backend
+            .sync_groups(username)
+            .await
+            .map_err(|()| Error::Internal)?;
+        let groups = req.apply(groups);
+
+        self.sync
+            .backend
+            .set_sync_groups(username, &groups)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        let now = now()?;
+        for group in &groups {
+            let mut subs = Vec::with_capacity(group.len());
+            for dev in group {
+                subs.push(self.current_subscriptions(Some(dev)).await?);
+            }
+            let all = subs.iter().flatten().collect::<HashSet<_>>();
+
+            for (dev, current) in group.iter().zip(&subs) {
+                let changes = SubscriptionChangesFromClient {
+                    add: all
+                        .iter()
+                        .filter(|url| !current.contains(url))
+                        .map(|url| url.to_string())
+                        .collect(),
+                    remove: vec![],
+                };
+                if changes.add.is_empty() {
+                    continue;
+                }
+
+                self.sync
+                    .backend
+                    .update_subscriptions(username, dev, &changes, now)
+                    .await
+                    .map_err(|()| Error::Internal)?;
+            }
+        }
+
+        self.sync_status().await
+    }
+
+    /// The other devices in `device_id`'s sync group
+    async fn sync_peers(&self, device_id: &str) -> Result<Vec<String>> {
+        let groups = self
+            .sync
+            .backend
+            .sync_groups(&self.username)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        Ok(groups
+            .into_iter()
+            .find(|group| group.iter().any(|dev| dev == device_id))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|dev| dev != device_id)
+            .collect())
+    }
+
+    fn settings_target(&self, scope: &str, query: QuerySettings) -> Result<SettingsTarget> {
+        let target = SettingsTarget::new(scope, query).map_err(|e| {
+            error!("{} settings: {e}", self.username);
+            Error::BadRequest
+        })?;
+
+        if let SettingsTarget::Device(ref device_id) = target {
+            if !crate::path_format::valid_name(device_id) {
+                error!(
+                    "{} settings for invalid device {device_id:?}",
+                    self.username
+                );
+                return Err(Error::BadRequest);
+            }
+            self.check_device(device_id)?;
+        }
+
+        // stored under the same URL as the subscription or episode action they describe
+        let sanitized = |url: String| {
+            sanitize(&url).ok_or_else(|| {
+                error!("{} settings for invalid URL {url:?}", self.username);
+                Error::BadRequest
+            })
+        };
+        Ok(match target {
+            SettingsTarget::Podcast(podcast) => SettingsTarget::Podcast(sanitized(podcast)?),
+            SettingsTarget::Episode { podcast, episode } => SettingsTarget::Episode {
+                podcast: sanitized(podcast)?,
+                episode: sanitized(episode)?,
+            },
+            target => target,
+        })
+    }
+
+    pub async fn settings(&self, scope: &str, query: QuerySettings) -> Result<Settings> {
+        let username = &self.username;
+        let target = self.settings_target(scope, query)?;
+        trace!("{username} getting settings for {target:?}");
+
+        self.sync
+            .backend
+            .settings(username, &target)
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    /// Apply `update`'s removals then its sets, returning the resulting settings
+    pub async fn update_settings(
+        &self,
+        scope: &str,
+        query: QuerySettings,
+        update: SettingsUpdate,
+    ) -> Result<Settings> {
+        let username = &self.username;
+        let target = self.settings_target(scope, query)?;
+        self.check_write()?;
+
+        info!(
+            "{username} updating settings for {target:?}: {} set, {} removed",
+            update.set.len(),
+            update.remove.len(),
+        );
+
+        self.sync
+            .backend
+            .update_settings(username, &target, &update)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        self.sync
+            .backend
+            .settings(username, &target)
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    pub async fn favorites(&self) -> Result<Vec<PodcastEpisode>> {
+        let username = &self.username;
+
+        let favorites = self
+            .sync
+            .backend
+            .favorites(username)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        info!("{username}, {} favorites", favorites.len());
+        Ok(favorites
+            .into_iter()
+            .map(|(podcast, episode)| PodcastEpisode::from_urls(podcast, episode))
+            .collect())
+    }
+
+    pub async fn podcast_lists(&self) -> Result<Vec<ListInfo>> {
+        self.sync
+            .backend
+            .podcast_lists(&self.username)
+            .await
+            .map_err(|()| Error::Internal)
+    }
 
-impl PodSyncAuthed<true> {
-    pub fn username(&self) -> &str {
-        &self.username
+    pub async fn podcast_list(&self, name: &str) -> Result<PodcastList> {
+        self.sync
+            .backend
+            .podcast_list(&self.username, name)
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })
     }
 
-    pub async fn logout(&self) -> Result<()> {
+    /// Create a list, named after its title, returning it and any URLs rewritten
+    pub async fn create_podcast_list(
+        &self,
+        title: &str,
+        public: bool,
+        urls: Vec<String>,
+    ) -> Result<(ListInfo, UpdatedList)> {
         let username = &self.username;
-        info!("{username} logout");
+        self.check_write()?;
+        check_list_title(title)?;
+
+        let name = podcast_list::name_from_title(title).ok_or_else(|| {
+            error!("{username} can't create a podcast list named after {title:?}");
+            Error::BadRequest
+        })?;
+        let mut update_urls = vec![];
+        let list = PodcastList {
+            info: ListInfo {
+                name,
+                title: title.to_string(),
+                public,
+            },
+            urls: sanitize_all(urls, &mut update_urls),
+        };
 
         self.sync
-            .0
-            .update_user(username, None)
+            .backend
+            .create_podcast_list(username, &list)
             .await
-            .then(|| ())
-            .ok_or(Error::Internal)
-    }
+            .map_err(|e| match e {
+                crate::backend::CreateError::AlreadyExists => Error::Conflict,
+                crate::backend::CreateError::Internal => Error::Internal,
+            })?;
 
-    pub fn session_id(&self) -> &SessionId {
-        &self.session_id
+        info!(
+            "{username} created podcast list {:?}, {} urls",
+            list.info.name,
+            list.urls.len()
+        );
+        Ok((list.info, UpdatedList { update_urls }))
     }
 
-    pub async fn devices(&self) -> Result<Vec<DeviceAndSub>> {
+    /// Replace a list's URLs, and optionally its title & visibility, returning any URLs rewritten
+    pub async fn update_podcast_list(
+        &self,
+        name: &str,
+        title: Option<String>,
+        public: Option<bool>,
+        urls: Vec<String>,
+    ) -> Result<UpdatedList> {
         let username = &self.username;
-        trace!("{username} getting devices");
+        self.check_write()?;
+        if let Some(ref title) = title {
+            check_list_title(title)?;
+        }
+
+        let mut list = self.podcast_list(name).await?;
+        list.info.title = title.unwrap_or(list.info.title);
+        list.info.public = public.unwrap_or(list.info.public);
+        let mut update_urls = vec![];
+        list.urls = sanitize_all(urls, &mut update_urls);
 
         self.sync
-            .0
-            .devices_for_user(username)
+            .backend
+            .update_podcast_list(username, &list)
             .await
-            .map(|devs| {
-                info!("{username}, {} devices", devs.len());
-                devs
-            })
-            .map_err(|()| Error::Internal)
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+
+        info!(
+            "{username} updated podcast list {name:?}, {} urls",
+            list.urls.len()
+        );
+        Ok(UpdatedList { update_urls })
     }
 
-    pub async fn update_device(&self, device_id: &str, update: DeviceUpdate) -> Result<()> {
+    pub async fn delete_podcast_list(&self, name: &str) -> Result<()> {
         let username = &self.username;
-        info!("{username} updating device {device_id}: {update:?}");
+        self.check_write()?;
 
         self.sync
-            .0
-            .update_device(username, device_id, update)
+            .backend
+            .delete_podcast_list(username, name)
             .await
-            .map_err(|()| Error::Internal)
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+
+        info!("{username} deleted podcast list {name:?}");
+        Ok(())
     }
 
     pub async fn subscriptions(
@@ -246,10 +1482,22 @@ impl PodSyncAuthed<true> {
         let username = &self.username;
 
         trace!("{username} on {device_id}, requesting subscription changes since {since}");
+        self.check_device(device_id)?;
+        self.note_device(device_id, ApiCall::Subscriptions).await?;
+
+        self.subscription_changes(device_id, since).await
+    }
+
+    async fn subscription_changes(
+        &self,
+        device_id: &str,
+        since: Timestamp,
+    ) -> Result<SubscriptionChangesToClient> {
+        let username = &self.username;
 
         let urls = self
             .sync
-            .0
+            .backend
             .subscriptions(username, device_id, since)
             .await
             .map_err(|()| Error::Internal)?;
@@ -300,6 +1548,29 @@ impl PodSyncAuthed<true> {
         })
     }
 
+    /// The URLs currently subscribed to, on `device_id`, or across all devices
+    pub async fn current_subscriptions(&self, device_id: Option<&str>) -> Result<Vec<String>> {
+        let username = &self.username;
+
+        if let Some(device_id) = device_id {
+            self.check_device(device_id)?;
+        }
+
+        let urls = self
+            .sync
+            .backend
+            .current_subscriptions(username, device_id)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        info!(
+            "{username} on {}, {} current subs",
+            device_id.unwrap_or("all devices"),
+            urls.len()
+        );
+        Ok(urls)
+    }
+
     pub async fn update_subscriptions(
         &self,
         device_id: &str,
@@ -309,26 +1580,105 @@ impl PodSyncAuthed<true> {
         let now = now()?;
 
         trace!("{username} updating subscription for device {device_id}");
+        self.check_write()?;
+        self.check_device(device_id)?;
+        self.note_device(device_id, ApiCall::Subscriptions).await?;
+
+        let mut update_urls = vec![];
+        let changes = SubscriptionChangesFromClient {
+            add: sanitize_all(changes.add, &mut update_urls),
+            remove: sanitize_all(changes.remove, &mut update_urls),
+        };
 
         self.sync
-            .0
+            .backend
             .update_subscriptions(username, device_id, &changes, now)
             .await
             .map_err(|()| Error::Internal)?;
 
+        for peer in self.sync_peers(device_id).await? {
+            let current = self
+                .sync
+                .backend
+                .current_subscriptions(username, Some(&peer))
+                .await
+                .map_err(|()| Error::Internal)?;
+
+            let peer_changes = SubscriptionChangesFromClient {
+                add: changes
+                    .add
+                    .iter()
+                    .filter(|url| !current.contains(url))
+                    .cloned()
+                    .collect(),
+                remove: changes
+                    .remove
+                    .iter()
+                    .filter(|url| current.contains(url))
+                    .cloned()
+                    .collect(),
+            };
+            if peer_changes.add.is_empty() && peer_changes.remove.is_empty() {
+                continue;
+            }
+
+            debug!("{username} on {device_id}, syncing subscription changes to {peer}");
+            self.sync
+                .backend
+                .update_subscriptions(username, &peer, &peer_changes, now)
+                .await
+                .map_err(|()| Error::Internal)?;
+        }
+
         Ok(UpdatedUrls {
             timestamp: now,
-            update_urls: changes
-                .add
-                .into_iter()
-                .map(|url| (url.clone(), url))
-                .collect(),
+            update_urls,
         })
     }
 
+    /// Replace a device's subscriptions, recording the difference as adds & removes
+    pub async fn replace_subscriptions(&self, device_id: &str, urls: Vec<String>) -> Result<()> {
+        self.check_write()?;
+        self.check_device(device_id)?;
+
+        let urls = sanitize_all(urls, &mut vec![]);
+        let current = self.current_subscriptions(Some(device_id)).await?;
+        let current = current.iter().collect::<HashSet<_>>();
+        let wanted = urls.iter().collect::<HashSet<_>>();
+
+        let changes = SubscriptionChangesFromClient {
+            add: wanted
+                .difference(&current)
+                .map(|url| url.to_string())
+                .collect(),
+            remove: current
+                .difference(&wanted)
+                .map(|url| url.to_string())
+                .collect(),
+        };
+
+        info!(
+            "{} on {device_id}, replacing subs: {} added, {} removed",
+            self.username,
+            changes.add.len(),
+            changes.remove.len(),
+        );
+        if changes.add.is_empty() && changes.remove.is_empty() {
+            return Ok(());
+        }
+
+        self.update_subscriptions(device_id, changes).await?;
+        Ok(())
+    }
+
     pub async fn episodes(&self, query: QueryEpisodes) -> Result<Episodes> {
         let username = &self.username;
 
+        // a device's token reads the whole account's actions, but can't ask for another device's
+        if let Some(ref device_id) = query.device {
+            self.check_device(device_id)?;
+        }
+
         trace!(
             "{username}, requesting episode changes since {:?}, device={}, podcast={}",
             query.since,
@@ -338,7 +1688,7 @@ impl PodSyncAuthed<true> {
 
         let episodes = self
             .sync
-            .0
+            .backend
             .episodes(username, &query)
             .await
             .map_err(|()| Error::Internal)?;
@@ -375,12 +1725,61 @@ impl PodSyncAuthed<true> {
         })
     }
 
+    /// Subscription changes for `device_id`, and episode changes for the
+    /// podcasts it's subscribed to, since `query.since`
+    pub async fn device_updates(
+        &self,
+        device_id: &str,
+        query: QueryUpdates,
+    ) -> Result<DeviceUpdates> {
+        let username = &self.username;
+        trace!(
+            "{username} on {device_id}, requesting updates since {}",
+            query.since
+        );
+        self.check_device(device_id)?;
+        self.note_device(device_id, ApiCall::Updates).await?;
+
+        let subs = self.subscription_changes(device_id, query.since).await?;
+        let current = self.current_subscriptions(Some(device_id)).await?;
+        let current = current.into_iter().collect::<HashSet<_>>();
+
+        let episodes = self
+            .episodes(QueryEpisodes {
+                since: Some(query.since),
+                ..Default::default()
+            })
+            .await?;
+
+        let updates = episodes
+            .actions
+            .into_iter()
+            .filter(|ep| current.contains(&ep.podcast))
+            .map(|ep| EpisodeUpdate::new(ep, query.include_actions))
+            .collect::<Vec<_>>();
+
+        info!(
+            "{username} on {device_id}, {} subs added, {} removed, {} episode updates",
+            subs.add.len(),
+            subs.remove.len(),
+            updates.len(),
+        );
+
+        Ok(DeviceUpdates {
+            add: subs.add.into_iter().map(Podcast::from_url).collect(),
+            rem: subs.remove,
+            updates,
+            timestamp: subs.timestamp.max(episodes.timestamp),
+        })
+    }
+
     pub async fn update_episodes(&self, body: Vec<Episode>) -> Result<UpdatedUrls> {
         let username = &self.username;
 
         trace!("{username} updating episodes");
+        self.check_write()?;
 
-        let changes = body
+        let mut changes = body
             .into_iter()
             .map(TryInto::try_into)
             .collect::<result::Result<Vec<Episode>, _>>()
@@ -389,20 +1788,260 @@ impl PodSyncAuthed<true> {
                 Error::BadRequest
             })?;
 
+        if let Some(TokenScope {
+            device: Some(ref allowed),
+            ..
+        }) = self.scope
+        {
+            for change in &mut changes {
+                match change.device {
+                    Some(ref d) => self.check_device(d)?,
+                    None => change.device = Some(allowed.clone()),
+                }
+            }
+        }
+
+        // actions with a rejected podcast or episode URL are dropped
+        let mut update_urls = vec![];
+        let changes = changes
+            .into_iter()
+            .filter_map(|mut change| {
+                change.podcast = sanitize_recorded(change.podcast, &mut update_urls)?;
+                change.episode = sanitize_recorded(change.episode, &mut update_urls)?;
+                Some(change)
+            })
+            .collect::<Vec<_>>();
+
+        let devices = changes
+            .iter()
+            .filter_map(|change| change.device.as_deref())
+            .collect::<HashSet<_>>();
+        for device_id in devices {
+            self.seen_device(device_id, ApiCall::Episodes).await?;
+        }
+
         let now = now()?;
         let change_count = changes.len();
 
         self.sync
-            .0
+            .backend
             .update_episodes(username, now, changes)
             .await
             .map_err(|()| Error::Internal)?;
 
         info!("{username} updated {change_count} episodes, timestamp {now}");
 
-        let update_timestamp = now;
-        Ok(UpdatedUrls::just_timestamp(update_timestamp))
+        Ok(UpdatedUrls {
+            timestamp: now,
+            update_urls,
+        })
+    }
+}
+
+impl<const USER_MATCH: bool> PodSyncAuthed<USER_MATCH> {
+    /// Admin operations need an admin's password or session, not an app token
+    pub async fn admin(self) -> Result<PodSyncAdmin> {
+        if self.scope.is_some() {
+            error!("{} app token used for admin operation", self.username);
+            return Err(Error::Forbidden);
+        }
+
+        let user = self.sync.find_enabled_user(&self.username).await?;
+        if !user.is_admin {
+            error!("{} isn't an admin", self.username);
+            return Err(Error::Forbidden);
+        }
+
+        Ok(PodSyncAdmin {
+            sync: self.sync,
+            username: self.username,
+        })
+    }
+}
+
+impl PodSyncAdmin {
+    pub async fn users(&self) -> Result<Vec<UserSummary>> {
+        self.sync
+            .backend
+            .user_summaries()
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    /// Every user's devices which haven't synced in `days` days, or ever
+    pub async fn stale_devices(&self, days: u32) -> Result<Vec<StaleDevice>> {
+        let cutoff = now()? - Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+        let mut stale = vec![];
+
+        for user in self
+            .sync
+            .backend
+            .users()
+            .await
+            .map_err(|()| Error::Internal)?
+        {
+            let devices = self
+                .sync
+                .backend
+                .devices_for_user(&user.username)
+                .await
+                .map_err(|()| Error::Internal)?;
+
+            stale.extend(
+                devices
+                    .into_iter()
+                    .filter(|dev| dev.activity.last_seen.is_none_or(|seen| seen < cutoff))
+                    .map(|device| StaleDevice {
+                        username: user.username.clone(),
+                        device,
+                    }),
+            );
+        }
+
+        info!(
+            "admin {} found {} devices unsynced for {days} days",
+            self.username,
+            stale.len()
+        );
+        Ok(stale)
+    }
+
+    /// Everyone's audit events, or just `username`'s
+    pub async fn audit_events(
+        &self,
+        username: Option<&str>,
+        since: Option<Timestamp>,
+    ) -> Result<Vec<AuditEvent>> {
+        if let Some(username) = username {
+            check_username(username)?;
+        }
+
+        self.sync
+            .backend
+            .audit_events(username, since.unwrap_or_else(Timestamp::zero))
+            .await
+            .map_err(|()| Error::Internal)
+    }
+
+    async fn find_user(&self, username: &str) -> Result<User> {
+        self.sync
+            .backend
+            .find_user(username)
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })
+    }
+
+    /// Refuse to act on our own account, so admins can't lock themselves out
+    fn check_other_user(&self, username: &str) -> Result<()> {
+        if username == self.username {
+            error!("admin {} tried to disable/delete themselves", self.username);
+            return Err(Error::Conflict);
+        }
+        Ok(())
+    }
+
+    /// Set a new password, ending the user's sessions
+    pub async fn reset_password(
+        &self,
+        username: &str,
+        password: &str,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        check_username(username)?;
+        self.find_user(username).await?;
+
+        check_new_password(username, password).map_err(|e| {
+            error!("can't reset {username:?}'s password: {e}");
+            Error::BadRequest
+        })?;
+
+        if !self
+            .sync
+            .backend
+            .update_pwhash(username, &pwhash(password))
+            .await
+        {
+            return Err(Error::Internal);
+        }
+        self.revoke_sessions(username).await?;
+
+        info!("admin {} reset {username}'s password", self.username);
+        self.sync
+            .audit(username, AuditAction::PasswordReset, true, client)
+            .await;
+        Ok(())
+    }
+
+    /// Disabling a user also ends their sessions
+    pub async fn set_disabled(&self, username: &str, disabled: bool) -> Result<()> {
+        check_username(username)?;
+        if disabled {
+            self.check_other_user(username)?;
+        }
+
+        self.sync
+            .backend
+            .set_user_disabled(username, disabled)
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+        if disabled {
+            self.revoke_sessions(username).await?;
+        }
+
+        info!(
+            "admin {} {} {username}",
+            self.username,
+            if disabled { "disabled" } else { "enabled" }
+        );
+        Ok(())
+    }
+
+    pub async fn revoke_sessions(&self, username: &str) -> Result<()> {
+        check_username(username)?;
+        self.find_user(username).await?;
+
+        self.sync
+            .backend
+            .delete_sessions_for_user(username, None)
+            .await
+            .map_err(|()| Error::Internal)?;
+
+        info!("admin {} ended {username}'s sessions", self.username);
+        Ok(())
+    }
+
+    pub async fn delete_user(&self, username: &str) -> Result<()> {
+        check_username(username)?;
+        self.check_other_user(username)?;
+
+        self.sync
+            .backend
+            .delete_user(username)
+            .await
+            .map_err(|e| match e {
+                crate::backend::FindError::NotFound => Error::NotFound,
+                crate::backend::FindError::Internal => Error::Internal,
+            })?;
+
+        info!("admin {} deleted {username}", self.username);
+        Ok(())
+    }
+}
+
+/// Usernames from admin paths can't name anyone unless they're valid
+fn check_username(username: &str) -> Result<()> {
+    if valid_username(username) {
+        return Ok(());
     }
+
+    error!("admin request for invalid username {username:?}");
+    Err(Error::NotFound)
 }
 
 #[derive(Debug)]
@@ -420,13 +2059,12 @@ fn now() -> Result<Timestamp> {
     })
 }
 
-impl UpdatedUrls {
-    pub fn just_timestamp(timestamp: Timestamp) -> Self {
-        Self {
-            timestamp,
-            update_urls: Default::default(),
-        }
+fn check_list_title(title: &str) -> Result<()> {
+    if title.trim().is_empty() || title.len() > 256 {
+        error!("invalid podcast list title {title:?}");
+        return Err(Error::BadRequest);
     }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -437,8 +2075,10 @@ mod test {
     use sqlx::{query, query_as};
     use uuid::Uuid;
 
+    use crate::auth::{AuthAttempt, BasicAuth};
     use crate::backend;
     use crate::episode::{EpisodeAction, Time};
+    use base64_light::base64_encode as base64;
 
     fn create_session() -> SessionId {
         Uuid::try_parse("550e8400-e29b-41d4-a716-446655440000")
@@ -448,19 +2088,202 @@ mod test {
 
     async fn create_podsync(username: &str) -> PodSyncAuthed<true> {
         let db = backend::test::create_db().await;
-        let podsync = Arc::new(PodSync(backend::Backend(db)));
+        let podsync = Arc::new(PodSync::new(backend::Backend(db), Config::default()));
         PodSyncAuthed {
             sync: podsync,
-            session_id: create_session(),
+            session: Some(AuthedSession {
+                id: create_session(),
+                device: None,
+            }),
+            scope: None,
             username: username.into(),
+            client: Default::default(),
         }
     }
 
+    fn auth_attempt(username: &str, pass: &str) -> AuthAttempt {
+        let header = format!("Basic {}", base64(&format!("{username}:{pass}")));
+        let auth: BasicAuth = header.parse().unwrap();
+        let Ok(attempt) = auth.with_path_username(username) else {
+            panic!("username mismatch");
+        };
+        attempt
+    }
+
+    #[tokio::test]
+    async fn legacy_pwhash_upgraded() {
+        let db = backend::test::create_db().await;
+
+        // given a user with a bare sha256 password hash:
+        let legacy = sha256::digest("abc");
+        query!(
+            r#"
+            INSERT INTO users
+            (username, pwhash)
+            VALUES ("bob", ?);
+            "#,
+            legacy,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let podsync = Arc::new(PodSync::new(backend::Backend(db), Config::default()));
+
+        // a wrong password is still rejected, and nothing is rehashed:
+        let r = podsync
+            .authenticate_basic(auth_attempt("bob", "abd"), &ClientInfo::default())
+            .await;
+        assert!(matches!(r, Err(Error::Unauthorized)));
+        assert_eq!(
+            podsync.backend.find_user("bob").await.unwrap().pwhash,
+            legacy
+        );
+
+        // the right password logs in and upgrades the stored hash:
+        podsync
+            .authenticate_basic(auth_attempt("bob", "abc"), &ClientInfo::default())
+            .await
+            .unwrap();
+
+        let pwhash = podsync.backend.find_user("bob").await.unwrap().pwhash;
+        assert!(pwhash.starts_with("$argon2id$"));
+
+        // which continues to work:
+        podsync
+            .authenticate_basic(auth_attempt("bob", "abc"), &ClientInfo::default())
+            .await
+            .unwrap();
+        assert!(matches!(
+            podsync
+                .authenticate_basic(auth_attempt("bob", "abd"), &ClientInfo::default())
+                .await,
+            Err(Error::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn disabled_user() {
+        let db = backend::test::create_db().await;
+        let podsync = Arc::new(PodSync::new(backend::Backend(db), Config::default()));
+
+        let pwhash = crate::auth::pwhash("abc");
+        podsync.backend.create_user("bob", &pwhash).await.unwrap();
+        podsync
+            .authenticate_basic(auth_attempt("bob", "abc"), &ClientInfo::default())
+            .await
+            .unwrap();
+
+        podsync
+            .backend
+            .set_user_disabled("bob", true)
+            .await
+            .unwrap();
+        assert!(matches!(
+            podsync
+                .authenticate_basic(auth_attempt("bob", "abc"), &ClientInfo::default())
+                .await,
+            Err(Error::Unauthorized)
+        ));
+        assert!(matches!(
+            podsync
+                .login(auth_attempt("bob", "abc"), None, &ClientInfo::default())
+                .await,
+            Err(Error::Unauthorized)
+        ));
+    }
+
+    #[tokio::test]
+    async fn session_expiry() {
+        let db = backend::test::create_db().await;
+
+        let pwhash = crate::auth::pwhash("abc");
+        query!(
+            r#"
+            INSERT INTO users
+            (username, pwhash)
+            VALUES ("bob", ?);
+            "#,
+            pwhash,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        let config = Config {
+            session_lifetime: Duration::from_secs(100),
+            session_idle_timeout: Duration::from_secs(50),
+            ..Default::default()
+        };
+        let podsync = Arc::new(PodSync::new(backend::Backend(db), config));
+        let pool = &podsync.backend.0;
+
+        let login = || async {
+            let authed = podsync
+                .login(auth_attempt("bob", "abc"), None, &ClientInfo::default())
+                .await
+                .unwrap();
+            let id = authed.session_id().unwrap().to_string();
+            id
+        };
+        let set = |id: String, last_used: i64, expires: i64| async move {
+            query!(
+                "UPDATE sessions SET last_used = ?, expires = ? WHERE id = ?",
+                last_used,
+                expires,
+                id,
+            )
+            .execute(pool)
+            .await
+            .unwrap();
+        };
+        let session = |id: &str| id.parse::<SessionId>().unwrap();
+
+        // now() is 25 under test, so a fresh session expires at 125
+        let id = login().await;
+        let found = podsync.backend.find_session(&id).await.unwrap();
+        assert_eq!(found.expires, Timestamp::from_i64(125));
+
+        // which isn't yet due for renewal
+        assert!(!podsync.renew_session(&session(&id)).await.unwrap());
+
+        // but is once it's past halfway
+        set(id.clone(), 25, 60).await;
+        assert!(podsync.renew_session(&session(&id)).await.unwrap());
+        let found = podsync.backend.find_session(&id).await.unwrap();
+        assert_eq!(found.expires, Timestamp::from_i64(125));
+        podsync
+            .authenticate(session(&id), &ClientInfo::default())
+            .await
+            .unwrap();
+
+        // sessions past their expiry are rejected and removed
+        set(id.clone(), 25, 25).await;
+        assert!(matches!(
+            podsync
+                .authenticate(session(&id), &ClientInfo::default())
+                .await,
+            Err(Error::Unauthorized)
+        ));
+        assert!(podsync.backend.find_session(&id).await.is_err());
+
+        // as are sessions left idle too long
+        let id = login().await;
+        set(id.clone(), -25, 125).await;
+        assert!(matches!(
+            podsync
+                .authenticate(session(&id), &ClientInfo::default())
+                .await,
+            Err(Error::Unauthorized)
+        ));
+        assert!(!podsync.renew_session(&session(&id)).await.unwrap());
+    }
+
     #[tokio::test]
     async fn episode_hashing() {
         let username = "user1";
-        let podcast = "pod1";
-        let episode = "ep1";
+        let podcast = "https://pod1.example";
+        let episode = "https://pod1.example/ep1";
         let device = "dev1";
 
         let podsync = create_podsync(username).await;
@@ -501,7 +2324,7 @@ mod test {
             podcast,
             episode,
         )
-        .execute(&podsync.sync.0 .0)
+        .execute(&podsync.sync.backend.0)
         .await
         .unwrap();
 
@@ -559,7 +2382,7 @@ mod test {
                 "#,
                 username
             )
-            .fetch_all(&podsync.sync.0 .0)
+            .fetch_all(&podsync.sync.backend.0)
             .await
             .unwrap()
         };
@@ -577,7 +2400,7 @@ mod test {
             };
 
             assert_eq!(modified, &Timestamp::now().unwrap());
-            assert!(hash.len() > 0); // default is ""
+            assert!(!hash.is_empty()); // default is ""
 
             new_hash = hash.clone();
         }
@@ -589,7 +2412,7 @@ mod test {
                 "UPDATE episodes SET modified = 23 WHERE username = ?",
                 username
             )
-            .execute(&podsync.sync.0 .0)
+            .execute(&podsync.sync.backend.0)
             .await
             .unwrap();
 
@@ -618,7 +2441,7 @@ mod test {
                 WHERE username = "u2"
                 "#
             )
-            .fetch_all(&podsync.sync.0 .0)
+            .fetch_all(&podsync.sync.backend.0)
             .await
             .unwrap();
 
@@ -0,0 +1,43 @@
+use std::net::IpAddr;
+
+use axum::http::{HeaderMap, HeaderName};
+use log::warn;
+
+use crate::client::ClientInfo;
+
+/// Authentication by a reverse proxy in front of us, which passes the
+/// authenticated username in a header
+pub struct Config {
+    /// e.g. `Remote-User`
+    pub header: HeaderName,
+    /// Only requests from these addresses may assert a username
+    pub trusted: Vec<IpAddr>,
+    /// Create users we haven't seen before, rather than rejecting them
+    pub auto_provision: bool,
+}
+
+impl Config {
+    /// The username asserted by a trusted proxy, if any
+    pub fn user<'h>(&self, headers: &'h HeaderMap, client: &ClientInfo) -> Option<&'h str> {
+        let value = headers.get(&self.header)?;
+
+        let trusted = client
+            .addr
+            .is_some_and(|addr| self.trusted.contains(&addr.to_canonical()));
+        if !trusted {
+            warn!(
+                "ignoring {} header from untrusted address {:?}",
+                self.header, client.addr
+            );
+            return None;
+        }
+
+        match value.to_str() {
+            Ok(user) if !user.trim().is_empty() => Some(user.trim()),
+            _ => {
+                warn!("ignoring invalid {} header", self.header);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use url::Url;
+
+/// Query parameters which only track where a link was followed from
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_") || matches!(key, "fbclid" | "gclid" | "mc_cid" | "mc_eid")
+}
+
+/// Normalise a podcast or episode URL, as mygpo does, so the same feed isn't stored
+/// under several spellings: trim whitespace, lowercase the scheme & host and drop
+/// tracking parameters. Returns `None` for URLs we won't store - empty or non-http(s).
+pub fn sanitize(url: &str) -> Option<String> {
+    let url = url.trim();
+    if url.is_empty() {
+        return None;
+    }
+
+    let mut parsed = Url::parse(url).ok()?;
+    if !matches!(parsed.scheme(), "http" | "https") || parsed.host_str().is_none_or(str::is_empty) {
+        return None;
+    }
+
+    // the kept parameters are left byte-for-byte, so signed feed URLs still verify
+    if let Some(query) = parsed.query() {
+        let pairs = query.split('&').collect::<Vec<_>>();
+        let kept = pairs
+            .iter()
+            .filter(|pair| {
+                let key = pair.split_once('=').map_or(**pair, |(key, _)| key);
+                !is_tracking_param(key)
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        if kept.len() != pairs.len() {
+            let kept = kept.join("&");
+            parsed.set_query((!kept.is_empty()).then_some(&kept));
+        }
+    }
+
+    let mut sanitized = String::from(parsed);
+    // `Url` gives bare hosts a "/" path, which clients don't send
+    if is_bare_host(&sanitized) {
+        sanitized.pop();
+    }
+    Some(sanitized)
+}
+
+fn is_bare_host(url: &str) -> bool {
+    url.strip_suffix('/')
+        .and_then(|u| u.split_once("://"))
+        .is_some_and(|(_, rest)| !rest.contains(['/', '?', '#']))
+}
+
+/// Sanitise `url`, recording it in `rewrites` as `(from, to)` if it changed,
+/// with rejected URLs rewritten to `""`
+pub fn sanitize_recorded(url: String, rewrites: &mut Vec<(String, String)>) -> Option<String> {
+    let new = sanitize(&url);
+
+    let rewritten = new.as_deref().unwrap_or("");
+    if rewritten != url && !rewrites.iter().any(|(from, _)| *from == url) {
+        rewrites.push((url, rewritten.to_string()));
+    }
+
+    new
+}
+
+/// Sanitise `urls` as [`sanitize_recorded`] does, dropping rejected URLs and duplicates
+pub fn sanitize_all(urls: Vec<String>, rewrites: &mut Vec<(String, String)>) -> Vec<String> {
+    let mut sanitized: Vec<String> = Vec::with_capacity(urls.len());
+
+    for url in urls {
+        if let Some(new) = sanitize_recorded(url, rewrites) {
+            if !sanitized.contains(&new) {
+                sanitized.push(new);
+            }
+        }
+    }
+
+    sanitized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sanitizes() {
+        for (from, to) in [
+            ("https://a.example/feed", Some("https://a.example/feed")),
+            ("https://a.example", Some("https://a.example")),
+            (
+                "  HTTP://Feed.Example/X.rss\n",
+                Some("http://feed.example/X.rss"),
+            ),
+            ("https://a.example/", Some("https://a.example")),
+            (
+                "https://a.example/feed?utm_source=x&id=1&fbclid=y",
+                Some("https://a.example/feed?id=1"),
+            ),
+            (
+                "https://a.example/feed?utm_medium=x",
+                Some("https://a.example/feed"),
+            ),
+            ("https://a.example/?id=1", Some("https://a.example/?id=1")),
+            (
+                "https://a.example/feed?b=2&a=1",
+                Some("https://a.example/feed?b=2&a=1"),
+            ),
+            (
+                "https://a.example/feed?utm_source=x&q=a%20b+c&sig=x%2By%3D",
+                Some("https://a.example/feed?q=a%20b+c&sig=x%2By%3D"),
+            ),
+            ("", None),
+            ("   ", None),
+            ("ftp://a.example/feed", None),
+            ("javascript:alert(1)", None),
+            ("a.example/feed", None),
+        ] {
+            assert_eq!(sanitize(from).as_deref(), to, "{from:?}");
+        }
+    }
+
+    #[test]
+    fn sanitizes_all() {
+        let mut rewrites = vec![];
+        let urls = sanitize_all(
+            vec![
+                "https://a.example".into(),
+                "https://A.example".into(),
+                "ftp://b.example".into(),
+            ],
+            &mut rewrites,
+        );
+
+        assert_eq!(urls, ["https://a.example"]);
+        assert_eq!(
+            rewrites,
+            [
+                ("https://A.example".into(), "https://a.example".into()),
+                ("ftp://b.example".into(), "".into()),
+            ]
+        );
+    }
+}
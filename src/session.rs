@@ -0,0 +1,13 @@
+use crate::time::Timestamp;
+
+#[derive(Debug)]
+pub struct Session {
+    pub id: String,
+    pub username: String,
+    pub device: Option<String>,
+    pub user_agent: Option<String>,
+    pub created: Timestamp,
+    pub last_used: Timestamp,
+    pub expires: Timestamp,
+    pub app_token: Option<String>, // hash of the token the session was created with
+}
@@ -0,0 +1,68 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// The episode setting marking an episode as a favourite, set to `true`
+pub const FAVORITE: &str = "is_favorite";
+
+/// A scope's settings: arbitrary JSON values, by key
+pub type Settings = Map<String, Value>;
+
+#[derive(Debug, Deserialize)]
+pub struct SettingsUpdate {
+    #[serde(default)]
+    pub set: Settings,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuerySettings {
+    pub device: Option<String>,
+    pub podcast: Option<String>,
+    pub episode: Option<String>,
+}
+
+/// What a set of settings applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingsTarget {
+    Account,
+    Device(String),
+    Podcast(String),
+    Episode { podcast: String, episode: String },
+}
+
+impl SettingsTarget {
+    /// Pick out the scope's target from the query, e.g. `device.json?device=phone`
+    pub fn new(scope: &str, query: QuerySettings) -> Result<Self, &'static str> {
+        let QuerySettings {
+            device,
+            podcast,
+            episode,
+        } = query;
+
+        match scope {
+            "account" => Ok(Self::Account),
+            "device" => device
+                .map(Self::Device)
+                .ok_or("device scope needs ?device="),
+            "podcast" => podcast
+                .map(Self::Podcast)
+                .ok_or("podcast scope needs ?podcast="),
+            "episode" => match (podcast, episode) {
+                (Some(podcast), Some(episode)) => Ok(Self::Episode { podcast, episode }),
+                _ => Err("episode scope needs ?podcast= and ?episode="),
+            },
+            _ => Err("unknown settings scope"),
+        }
+    }
+
+    /// The (scope, target, episode) triple the backends store settings under
+    pub fn key(&self) -> (&'static str, &str, &str) {
+        match self {
+            Self::Account => ("account", "", ""),
+            Self::Device(device) => ("device", device, ""),
+            Self::Podcast(podcast) => ("podcast", podcast, ""),
+            Self::Episode { podcast, episode } => ("episode", podcast, episode),
+        }
+    }
+}
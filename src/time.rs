@@ -1,10 +1,10 @@
-use std::{fmt, time};
+use std::{fmt, ops, time};
 
 use ::time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use log::error;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
 #[cfg_attr(feature = "backend-sql", derive(sqlx::Type), sqlx(transparent))]
 pub struct Timestamp(i64);
@@ -37,6 +37,24 @@ impl Timestamp {
     }
 }
 
+impl ops::Add<time::Duration> for Timestamp {
+    type Output = Self;
+
+    fn add(self, duration: time::Duration) -> Self {
+        let secs = i64::try_from(duration.as_secs()).unwrap_or(i64::MAX);
+        Self(self.0.saturating_add(secs))
+    }
+}
+
+impl ops::Sub<time::Duration> for Timestamp {
+    type Output = Self;
+
+    fn sub(self, duration: time::Duration) -> Self {
+        let secs = i64::try_from(duration.as_secs()).unwrap_or(i64::MAX);
+        Self(self.0.saturating_sub(secs))
+    }
+}
+
 impl fmt::Display for Timestamp {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.0 == 0 {
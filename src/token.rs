@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::time::Timestamp;
+
+/// App tokens are distinguishable from passwords, so we can skip password hashing for them
+const PREFIX: &str = "podsync_";
+
+#[derive(Debug)]
+#[serde_with::skip_serializing_none]
+#[derive(Serialize)]
+pub struct AppToken {
+    #[serde(skip)]
+    pub username: String,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub device: Option<String>,
+    pub read_only: bool,
+    pub created: Timestamp,
+    pub last_used: Option<Timestamp>,
+}
+
+/// What an app token restricts its bearer to
+#[derive(Debug, Clone)]
+pub struct TokenScope {
+    pub device: Option<String>,
+    pub read_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    pub device: Option<String>,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NewToken {
+    pub name: String,
+    pub token: String, // only ever shown once
+}
+
+impl AppToken {
+    pub fn scope(&self) -> TokenScope {
+        TokenScope {
+            device: self.device.clone(),
+            read_only: self.read_only,
+        }
+    }
+}
+
+pub fn generate() -> String {
+    format!(
+        "{PREFIX}{}{}",
+        Uuid::new_v4().as_simple(),
+        Uuid::new_v4().as_simple()
+    )
+}
+
+pub fn is_token(s: &str) -> bool {
+    s.starts_with(PREFIX)
+}
+
+/// Tokens are long and random, so a plain digest is enough to protect them at rest
+pub fn hash(token: &str) -> String {
+    sha256::digest(token)
+}
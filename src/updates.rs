@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+use crate::episode::{Episode, EpisodeActionRaw};
+use crate::podcast::{Podcast, PodcastEpisode};
+use crate::time::Timestamp;
+
+/// Everything a device needs to catch up, in one response
+#[derive(Debug, Serialize)]
+pub struct DeviceUpdates {
+    pub add: Vec<Podcast>,
+    pub rem: Vec<String>,
+    pub updates: Vec<EpisodeUpdate>,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EpisodeUpdate {
+    #[serde(flatten)]
+    pub episode: PodcastEpisode,
+    pub status: EpisodeActionRaw,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<Episode>,
+}
+
+impl EpisodeUpdate {
+    pub fn new(episode: Episode, include_action: bool) -> Self {
+        let (status, _, _, _) = episode.action.clone().into();
+
+        Self {
+            episode: PodcastEpisode::from_urls(episode.podcast.clone(), episode.episode.clone()),
+            status,
+            action: include_action.then_some(episode),
+        }
+    }
+}
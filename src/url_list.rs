@@ -0,0 +1,183 @@
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+use log::error;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use serde::Serialize;
+
+use crate::path_format::Format;
+use crate::podcast::Podcast;
+use crate::podsync::{Error, Result};
+
+/// Render podcast URLs in one of the gpodder Simple API's formats
+pub fn render(format: Format, urls: &[String], jsonp: Option<&str>) -> Result<Response> {
+    render_with_json(format, urls, || json(urls), jsonp)
+}
+
+/// As [`render`], but `json` & `jsonp` describe each podcast in full
+pub fn render_podcasts(
+    format: Format,
+    podcasts: &[Podcast],
+    jsonp: Option<&str>,
+) -> Result<Response> {
+    let urls = podcasts.iter().map(|p| p.url.clone()).collect::<Vec<_>>();
+    render_with_json(format, &urls, || json(podcasts), jsonp)
+}
+
+fn render_with_json(
+    format: Format,
+    urls: &[String],
+    json: impl FnOnce() -> Result<String>,
+    jsonp: Option<&str>,
+) -> Result<Response> {
+    let (content_type, body) = match format {
+        Format::Json => ("application/json", json()?),
+        Format::Jsonp => {
+            let callback = jsonp.ok_or_else(|| {
+                error!("jsonp requested without a callback");
+                Error::BadRequest
+            })?;
+            if !valid_callback(callback) {
+                error!("invalid jsonp callback {callback:?}");
+                return Err(Error::BadRequest);
+            }
+
+            ("application/javascript", format!("{callback}({})", json()?))
+        }
+        Format::Txt => (
+            "text/plain",
+            urls.iter().map(|u| format!("{u}\n")).collect(),
+        ),
+        Format::Opml => ("text/x-opml", opml(urls)),
+        Format::Xml => ("application/xml", xml(urls)),
+    };
+
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+/// Parse an uploaded list of podcast URLs. Only `json`, `opml` and `txt` may be uploaded.
+pub fn parse(format: Format, body: &str) -> Result<Vec<String>> {
+    let urls = match format {
+        Format::Json => serde_json::from_str(body).map_err(|e| {
+            error!("couldn't parse json url list: {e}");
+            Error::BadRequest
+        })?,
+        Format::Txt => body
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Format::Opml => parse_opml(body)?,
+        Format::Jsonp | Format::Xml => {
+            error!("can't upload {format:?}");
+            return Err(Error::BadRequest);
+        }
+    };
+
+    Ok(urls)
+}
+
+/// Every `<outline xmlUrl="...">`, at any depth, so categorised feeds are included
+fn parse_opml(body: &str) -> Result<Vec<String>> {
+    let mut reader = quick_xml::Reader::from_str(body);
+    reader.config_mut().check_end_names = true;
+
+    let mut urls = vec![];
+    let mut depth = 0usize;
+    let mut seen_opml = false;
+
+    loop {
+        let event = reader.read_event().map_err(|e| {
+            error!("couldn't parse opml: {e}");
+            Error::BadRequest
+        })?;
+
+        let e = match event {
+            Event::Start(e) => {
+                depth += 1;
+                e
+            }
+            Event::Empty(e) => e,
+            Event::End(_) => {
+                depth -= 1;
+                continue;
+            }
+            // a truncated upload would otherwise drop subscriptions
+            Event::Eof if depth > 0 || !seen_opml => {
+                error!("incomplete opml");
+                return Err(Error::BadRequest);
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        match e.local_name().as_ref() {
+            b"opml" => seen_opml = true,
+            b"outline" => {
+                let attr = e.try_get_attribute("xmlUrl").map_err(|e| {
+                    error!("couldn't parse opml outline: {e}");
+                    Error::BadRequest
+                })?;
+                let Some(attr) = attr else {
+                    continue;
+                };
+
+                let url = attr.unescape_value().map_err(|e| {
+                    error!("couldn't parse opml xmlUrl: {e}");
+                    Error::BadRequest
+                })?;
+                urls.push(url.trim().to_string());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(urls)
+}
+
+fn json(podcasts: &(impl Serialize + ?Sized)) -> Result<String> {
+    serde_json::to_string(podcasts).map_err(|e| {
+        error!("couldn't serialise podcasts: {e:?}");
+        Error::Internal
+    })
+}
+
+/// Only allow identifiers, so the callback can't inject script
+fn valid_callback(callback: &str) -> bool {
+    !callback.is_empty()
+        && callback
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '$' | '.'))
+}
+
+fn opml(urls: &[String]) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<opml version=\"2.0\">\n",
+        "  <head>\n",
+        "    <title>podsync subscriptions</title>\n",
+        "  </head>\n",
+        "  <body>\n",
+    ));
+    for url in urls {
+        let url = escape(url);
+        out += &format!("    <outline text=\"{url}\" type=\"rss\" xmlUrl=\"{url}\"/>\n");
+    }
+    out += "  </body>\n</opml>\n";
+    out
+}
+
+fn xml(urls: &[String]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<podcasts>\n");
+    for url in urls {
+        out += &format!(
+            "  <podcast>\n    <url>{}</url>\n  </podcast>\n",
+            escape(url)
+        );
+    }
+    out += "</podcasts>\n";
+    out
+}
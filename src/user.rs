@@ -1,7 +1,36 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug)]
 #[cfg_attr(feature = "backend-sql", derive(sqlx::Type))]
 pub struct User {
     pub username: String,
     pub pwhash: String,
-    pub session_id: Option<String>,
+    pub disabled: bool,
+    pub is_admin: bool,
+}
+
+/// A user, as listed by admins
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub username: String,
+    pub disabled: bool,
+    pub is_admin: bool,
+    pub devices: u32,
+    /// Distinct podcasts subscribed to, across all devices
+    pub subscriptions: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewPassword {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+pub fn valid_username(username: &str) -> bool {
+    crate::path_format::valid_name(username)
 }